@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::sync::{Mutex, OnceLock};
+
+// Lookups go through NSS (getpwuid_r and friends) so LDAP/SSSD accounts resolve
+// too. Results are cached because every file in a tree asks about the same few
+// owners.
+static USER_NAMES: OnceLock<Mutex<HashMap<u32, Option<String>>>> = OnceLock::new();
+static GROUP_NAMES: OnceLock<Mutex<HashMap<u32, Option<String>>>> = OnceLock::new();
+
+/// Name of the user with `uid`, or `None` if the passwd database has no entry.
+pub fn user_name(uid: u32) -> Option<String> {
+    cached(&USER_NAMES, uid, || lookup_user_by_uid(uid))
+}
+
+/// Name of the group with `gid`, or `None` if the group database has no entry.
+pub fn group_name(gid: u32) -> Option<String> {
+    cached(&GROUP_NAMES, gid, || lookup_group_by_gid(gid))
+}
+
+/// Resolves a user name to its uid.
+pub fn uid_for_name(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    lookup(
+        |pwd: &mut libc::passwd, buf, result| unsafe {
+            libc::getpwnam_r(name.as_ptr(), pwd, buf.as_mut_ptr(), buf.len(), result)
+        },
+        |pwd| pwd.pw_uid,
+    )
+}
+
+/// Resolves a group name to its gid.
+pub fn gid_for_name(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    lookup(
+        |grp: &mut libc::group, buf, result| unsafe {
+            libc::getgrnam_r(name.as_ptr(), grp, buf.as_mut_ptr(), buf.len(), result)
+        },
+        |grp| grp.gr_gid,
+    )
+}
+
+fn cached<F>(
+    cache: &OnceLock<Mutex<HashMap<u32, Option<String>>>>,
+    id: u32,
+    lookup: F,
+) -> Option<String>
+where
+    F: FnOnce() -> Option<String>,
+{
+    let mut cache = cache
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    cache.entry(id).or_insert_with(lookup).clone()
+}
+
+fn lookup_user_by_uid(uid: u32) -> Option<String> {
+    lookup(
+        |pwd: &mut libc::passwd, buf, result| unsafe {
+            libc::getpwuid_r(uid, pwd, buf.as_mut_ptr(), buf.len(), result)
+        },
+        |pwd| {
+            unsafe { CStr::from_ptr(pwd.pw_name) }
+                .to_string_lossy()
+                .into_owned()
+        },
+    )
+}
+
+fn lookup_group_by_gid(gid: u32) -> Option<String> {
+    lookup(
+        |grp: &mut libc::group, buf, result| unsafe {
+            libc::getgrgid_r(gid, grp, buf.as_mut_ptr(), buf.len(), result)
+        },
+        |grp| {
+            unsafe { CStr::from_ptr(grp.gr_name) }
+                .to_string_lossy()
+                .into_owned()
+        },
+    )
+}
+
+// Runs one of the reentrant *_r calls, growing the string buffer on ERANGE.
+// The entry's strings point into the buffer, so `extract` copies what it needs
+// out before the buffer is dropped.
+fn lookup<T, U, C, E>(mut call: C, extract: E) -> Option<U>
+where
+    C: FnMut(&mut T, &mut Vec<libc::c_char>, *mut *mut T) -> libc::c_int,
+    E: FnOnce(&T) -> U,
+{
+    // SAFETY: passwd and group are plain C structs for which all-zero is valid
+    let mut entry: T = unsafe { std::mem::zeroed() };
+    let mut buf: Vec<libc::c_char> = vec![0; 1024];
+    loop {
+        let mut result: *mut T = std::ptr::null_mut();
+        let rc = call(&mut entry, &mut buf, &mut result);
+        if rc == libc::ERANGE && buf.len() < 1 << 20 {
+            let len = buf.len() * 2;
+            buf.resize(len, 0);
+            continue;
+        }
+        if rc != 0 || result.is_null() {
+            return None;
+        }
+        return Some(extract(&entry));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_root_user_and_group() {
+        assert_eq!(user_name(0).as_deref(), Some("root"));
+        assert_eq!(uid_for_name("root"), Some(0));
+        assert_eq!(gid_for_name("root"), Some(0));
+        assert_eq!(group_name(0).as_deref(), Some("root"));
+    }
+
+    #[test]
+    fn test_unknown_names() {
+        assert_eq!(uid_for_name("no-such-user-sparkle"), None);
+        assert_eq!(gid_for_name("no-such-group-sparkle"), None);
+        assert_eq!(uid_for_name("bad\0name"), None);
+    }
+}
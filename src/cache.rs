@@ -0,0 +1,411 @@
+use crate::config::FileTypes;
+use crate::content_info::{ContentInfo, MediaInfo};
+use crate::file_metadata::FileType;
+use crate::state;
+use std::collections::HashMap;
+use std::ffi::OsStr;
+use std::fs::{self, File, Metadata};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+const HEADER: &str = "sparkle-cache 2";
+// bytes hashed at each end of a file
+const HASH_SPAN: u64 = 64 * 1024;
+
+/// Identifies one version of a file: rewriting it changes the size or the
+/// modification time, replacing it the inode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stamp {
+    pub inode: u64,
+    pub size: u64,
+    pub mtime: i64,
+    pub mtime_nsec: i64,
+}
+
+impl Stamp {
+    pub fn of(metadata: &Metadata) -> Stamp {
+        Stamp {
+            inode: metadata.ino(),
+            size: metadata.len(),
+            mtime: metadata.mtime(),
+            mtime_nsec: metadata.mtime_nsec(),
+        }
+    }
+}
+
+/// What was detected about a file the last time it was searched. Only the
+/// parts of `ContentInfo` that filters look at are kept.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub stamp: Stamp,
+    // of the first and last 64 KiB, regular files only. Only worked out once
+    // a file changed without changing its size.
+    pub hash: Option<u64>,
+    pub file_type: FileType,
+    // whether encoding and language, and the EXIF data were looked at
+    pub text_detected: bool,
+    pub media_detected: bool,
+    pub encoding: Option<String>,
+    pub language: Option<String>,
+    pub taken: Option<SystemTime>,
+}
+
+impl Entry {
+    pub fn new(
+        stamp: Stamp,
+        hash: Option<u64>,
+        file_type: &FileType,
+        content_info: Option<&ContentInfo>,
+    ) -> Entry {
+        Entry {
+            stamp,
+            hash,
+            file_type: file_type.clone(),
+            text_detected: content_info.is_some_and(|info| info.text_detected),
+            media_detected: content_info.is_some_and(|info| info.media_detected),
+            encoding: content_info.and_then(|info| info.text_encoding.clone()),
+            language: content_info.and_then(|info| info.language.clone()),
+            taken: content_info
+                .and_then(|info| info.media_info.as_ref())
+                .and_then(|media| media.date_taken),
+        }
+    }
+
+    pub fn content_info(&self, path: &Path) -> ContentInfo {
+        let mut info = ContentInfo::from_name(path);
+        info.text_detected = self.text_detected;
+        info.media_detected = self.media_detected;
+        info.text_encoding = self.encoding.clone();
+        info.language = self.language.clone();
+        if self.taken.is_some() {
+            info.media_info = Some(MediaInfo {
+                width: None,
+                height: None,
+                duration: None,
+                date_taken: self.taken,
+                camera_make: None,
+                camera_model: None,
+                gps_coordinates: None,
+            });
+            info.has_metadata = true;
+        }
+        info
+    }
+}
+
+/// Detection results of one rule's last search of one location. Entries are
+/// looked up in what the last run saved and collected anew by this one, so
+/// files that disappeared drop out when the cache is saved.
+#[derive(Debug)]
+pub struct Cache {
+    path: PathBuf,
+    fingerprint: u64,
+    previous: HashMap<PathBuf, Entry>,
+    current: HashMap<PathBuf, Entry>,
+}
+
+impl Cache {
+    /// A missing or corrupt cache, or one written under other `[file_types]`
+    /// mappings, starts out empty.
+    pub fn load(path: &Path, file_types: &FileTypes) -> io::Result<Cache> {
+        let mut cache = Cache {
+            path: path.to_path_buf(),
+            fingerprint: file_types.fingerprint(),
+            previous: HashMap::new(),
+            current: HashMap::new(),
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(cache),
+            Err(e) => return Err(e),
+        };
+        let mut lines = BufReader::new(file).split(b'\n');
+        let header = lines.next().transpose()?.unwrap_or_default();
+        if header != cache.header().as_bytes() {
+            tracing::info!("Discarding outdated cache {}", path.display());
+            return Ok(cache);
+        }
+        for line in lines {
+            let Some((path, entry)) = parse_line(&line?) else {
+                tracing::warn!("Discarding corrupt cache {}", path.display());
+                cache.previous.clear();
+                break;
+            };
+            cache.previous.insert(path, entry);
+        }
+        Ok(cache)
+    }
+
+    /// Writes the entries of this run. Replaces the file atomically, so a
+    /// crash never leaves half a cache.
+    pub fn save(&self) -> io::Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let temp = self.path.with_extension("tmp");
+        let mut out = BufWriter::new(File::create(&temp)?);
+        writeln!(out, "{}", self.header())?;
+        for (path, entry) in &self.current {
+            write_line(&mut out, path, entry)?;
+        }
+        out.into_inner().map_err(|e| e.into_error())?.sync_all()?;
+        fs::rename(temp, &self.path)
+    }
+
+    fn header(&self) -> String {
+        format!("{} {:016x}", HEADER, self.fingerprint)
+    }
+
+    /// The last run's entry for `path`, if the file has not changed since.
+    pub fn get(&self, path: &Path, stamp: &Stamp) -> Option<&Entry> {
+        self.previous
+            .get(path)
+            .filter(|entry| entry.stamp == *stamp)
+    }
+
+    /// The last run's entry for `path` if the file was touched or replaced
+    /// but still has the same content.
+    pub fn get_by_hash(&self, path: &Path, size: u64, hash: u64) -> Option<&Entry> {
+        self.previous
+            .get(path)
+            .filter(|entry| entry.stamp.size == size && entry.hash == Some(hash))
+    }
+
+    /// Whether the last run saw `path` at `size`, the only case in which
+    /// `get_by_hash` can find it, so the file is worth hashing.
+    pub fn has_size(&self, path: &Path, size: u64) -> bool {
+        self.previous
+            .get(path)
+            .is_some_and(|entry| entry.stamp.size == size)
+    }
+
+    pub fn insert(&mut self, path: PathBuf, entry: Entry) {
+        self.current.insert(path, entry);
+    }
+}
+
+/// Hashes the size and both ends of a file, which tells a touched file from
+/// a rewritten one without reading all of it.
+pub fn partial_hash(path: &Path) -> io::Result<u64> {
+    let mut file = File::open(path)?;
+    let size = file.metadata()?.len();
+    let mut bytes = size.to_le_bytes().to_vec();
+    (&mut file).take(HASH_SPAN).read_to_end(&mut bytes)?;
+    if size > HASH_SPAN {
+        file.seek(SeekFrom::Start(HASH_SPAN.max(size - HASH_SPAN)))?;
+        file.take(HASH_SPAN).read_to_end(&mut bytes)?;
+    }
+    Ok(state::fnv1a(&bytes))
+}
+
+// One entry per line, tab separated, the path last:
+// inode size mtime mtime_nsec hash file_type detected encoding language taken
+// path, where detected has `t` when encoding and language and `m` when the EXIF
+// data were looked at
+// Empty fields are unset. Tabs, newlines and backslashes are escaped.
+fn write_line<W: Write>(out: &mut W, path: &Path, entry: &Entry) -> io::Result<()> {
+    let stamp = &entry.stamp;
+    write!(
+        out,
+        "{}\t{}\t{}\t{}\t{}\t",
+        stamp.inode,
+        stamp.size,
+        stamp.mtime,
+        stamp.mtime_nsec,
+        entry
+            .hash
+            .map(|hash| format!("{:016x}", hash))
+            .unwrap_or_default()
+    )?;
+    for field in [
+        Some(entry.file_type.as_str()),
+        Some(match (entry.text_detected, entry.media_detected) {
+            (true, true) => "tm",
+            (true, false) => "t",
+            (false, true) => "m",
+            (false, false) => "",
+        }),
+        entry.encoding.as_deref(),
+        entry.language.as_deref(),
+    ] {
+        out.write_all(&escape(field.unwrap_or_default().as_bytes()))?;
+        out.write_all(b"\t")?;
+    }
+    let taken = entry
+        .taken
+        .and_then(|taken| taken.duration_since(UNIX_EPOCH).ok())
+        .map(|since| since.as_secs().to_string())
+        .unwrap_or_default();
+    write!(out, "{}\t", taken)?;
+    out.write_all(&escape(path.as_os_str().as_bytes()))?;
+    out.write_all(b"\n")
+}
+
+fn parse_line(line: &[u8]) -> Option<(PathBuf, Entry)> {
+    let fields: Vec<Vec<u8>> = line.split(|byte| *byte == b'\t').map(unescape).collect();
+    let [
+        inode,
+        size,
+        mtime,
+        mtime_nsec,
+        hash,
+        file_type,
+        detected,
+        encoding,
+        language,
+        taken,
+        path,
+    ] = fields.as_slice()
+    else {
+        return None;
+    };
+    let text = |field: &[u8]| String::from_utf8(field.to_vec()).ok();
+    let optional = |field: &[u8]| text(field).filter(|value| !value.is_empty());
+    let number = |field: &[u8]| text(field)?.parse().ok();
+
+    let entry = Entry {
+        stamp: Stamp {
+            inode: number(inode)?,
+            size: number(size)?,
+            mtime: text(mtime)?.parse().ok()?,
+            mtime_nsec: text(mtime_nsec)?.parse().ok()?,
+        },
+        hash: match optional(hash) {
+            Some(hash) => Some(u64::from_str_radix(&hash, 16).ok()?),
+            None => None,
+        },
+        file_type: FileType::from(text(file_type)?),
+        text_detected: detected.contains(&b't'),
+        media_detected: detected.contains(&b'm'),
+        encoding: optional(encoding),
+        language: optional(language),
+        taken: match optional(taken) {
+            Some(secs) => Some(UNIX_EPOCH + Duration::from_secs(secs.parse().ok()?)),
+            None => None,
+        },
+    };
+    Some((PathBuf::from(OsStr::from_bytes(path)), entry))
+}
+
+fn escape(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    for byte in bytes {
+        match byte {
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            _ => out.push(*byte),
+        }
+    }
+    out
+}
+
+fn unescape(bytes: &[u8]) -> Vec<u8> {
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut bytes = bytes.iter();
+    while let Some(byte) = bytes.next() {
+        if *byte != b'\\' {
+            out.push(*byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(b't') => out.push(b'\t'),
+            Some(b'n') => out.push(b'\n'),
+            Some(other) => out.push(*other),
+            None => out.push(b'\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn entry(size: u64) -> Entry {
+        Entry {
+            stamp: Stamp {
+                inode: 42,
+                size,
+                mtime: 1_700_000_000,
+                mtime_nsec: 5,
+            },
+            hash: Some(0xdead_beef),
+            file_type: FileType::Text,
+            text_detected: true,
+            media_detected: true,
+            encoding: Some("UTF-8".to_string()),
+            language: None,
+            taken: Some(UNIX_EPOCH + Duration::from_secs(1_600_000_000)),
+        }
+    }
+
+    #[test]
+    fn test_cache_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("cache").join("downloads.cache");
+        let file_types = FileTypes::default();
+        let odd_name = PathBuf::from("/data/tab\there\nand \\ back");
+
+        let mut cache = Cache::load(&path, &file_types).unwrap();
+        cache.insert(odd_name.clone(), entry(10));
+        cache.insert(PathBuf::from("/data/notes.txt"), entry(20));
+        cache.save().unwrap();
+
+        let cache = Cache::load(&path, &file_types).unwrap();
+        let stamp = entry(10).stamp;
+        assert_eq!(cache.get(&odd_name, &stamp), Some(&entry(10)));
+        assert_eq!(cache.get(Path::new("/data/notes.txt"), &stamp), None);
+        assert_eq!(
+            cache.get_by_hash(Path::new("/data/notes.txt"), 20, 0xdead_beef),
+            Some(&entry(20))
+        );
+        assert!(cache.has_size(Path::new("/data/notes.txt"), 20));
+        assert!(!cache.has_size(Path::new("/data/notes.txt"), 10));
+        assert!(!cache.has_size(Path::new("/data/other.txt"), 20));
+
+        // entries not seen again are dropped on the next save
+        cache.save().unwrap();
+        let cache = Cache::load(&path, &file_types).unwrap();
+        assert_eq!(cache.get(&odd_name, &stamp), None);
+    }
+
+    #[test]
+    fn test_cache_discarded_when_file_types_change() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("downloads.cache");
+
+        let mut cache = Cache::load(&path, &FileTypes::default()).unwrap();
+        cache.insert(PathBuf::from("/data/notes.txt"), entry(10));
+        cache.save().unwrap();
+
+        let file_types: FileTypes = toml::from_str("code = [\"txt\"]").unwrap();
+        let cache = Cache::load(&path, &file_types).unwrap();
+        assert_eq!(
+            cache.get(Path::new("/data/notes.txt"), &entry(10).stamp),
+            None
+        );
+    }
+
+    #[test]
+    fn test_partial_hash() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("large.bin");
+        let mut contents = vec![0u8; 3 * HASH_SPAN as usize];
+        fs::write(&path, &contents).unwrap();
+        let hash = partial_hash(&path).unwrap();
+
+        // the middle is not hashed, the ends are
+        contents[HASH_SPAN as usize + 1] = 1;
+        fs::write(&path, &contents).unwrap();
+        assert_eq!(partial_hash(&path).unwrap(), hash);
+        let last = contents.len() - 1;
+        contents[last] = 1;
+        fs::write(&path, &contents).unwrap();
+        assert_ne!(partial_hash(&path).unwrap(), hash);
+    }
+}
@@ -0,0 +1,365 @@
+use crate::config::{Action, Config, Filter, Rule};
+use crate::format::{Format, ParseError};
+use std::ffi::CString;
+use std::fmt;
+use std::fs;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    // the configuration does not load, or a rule fails when it runs
+    Error,
+    // a rule that probably does something other than what was meant
+    Warning,
+}
+
+/// One problem `check` found in a configuration file.
+#[derive(Debug)]
+pub struct Finding {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl fmt::Display for Finding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let label = match self.severity {
+            Severity::Error => "error",
+            Severity::Warning => "warning",
+        };
+        write!(f, "{}: {}", label, self.message)
+    }
+}
+
+fn error(message: String) -> Finding {
+    Finding {
+        severity: Severity::Error,
+        message,
+    }
+}
+
+fn warning(message: String) -> Finding {
+    Finding {
+        severity: Severity::Warning,
+        message,
+    }
+}
+
+/// Loads the configuration at `path` the way a run would and reports
+/// everything that would stop it or make it misbehave, without running any
+/// rule. Stops at the first syntax or type error, including unknown filter
+/// keys, as nothing after it can be checked.
+pub fn check(path: &Path, format: Format) -> Vec<Finding> {
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => return vec![error(format!("cannot read {}: {}", path.display(), e))],
+    };
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let mut ignored = Vec::new();
+    let (config, legacy) = match Config::parse(&contents, format, dir, |key| ignored.push(key)) {
+        Ok(read) => read,
+        Err(e) => return vec![parse_error(path, &e)],
+    };
+
+    let mut findings: Vec<Finding> = ignored
+        .into_iter()
+        .map(|key| warning(format!("unknown key `{}` is ignored", key)))
+        .chain(legacy.into_iter().map(warning))
+        .collect();
+    if let Err(e) = config.validate() {
+        findings.push(error(e));
+    }
+    for rule in &config.rules {
+        check_paths(rule, &mut findings);
+        check_accounts(rule, &mut findings);
+        check_deletes(rule, &mut findings);
+    }
+    findings
+}
+
+// e.g. `config.toml:3:10: invalid type: string "yes", expected a boolean`
+fn parse_error(path: &Path, e: &ParseError) -> Finding {
+    let path = e.file.as_deref().unwrap_or(path);
+    match e.position {
+        Some((line, column)) => error(format!(
+            "{}:{}:{}: {}",
+            path.display(),
+            line,
+            column,
+            e.message
+        )),
+        None => error(format!("{}: {}", path.display(), e.message)),
+    }
+}
+
+fn check_paths(rule: &Rule, findings: &mut Vec<Finding>) {
+    let changes_location = rule.actions.iter().any(|action| {
+        matches!(
+            action,
+            Action::Move(_) | Action::Rename { .. } | Action::Delete
+        )
+    });
+    for location in &rule.locations {
+        let path = &location.path;
+        if !path.exists() {
+            findings.push(error(format!(
+                "rule '{}': location {} does not exist",
+                rule.name,
+                path.display()
+            )));
+        } else if !path.is_dir() {
+            findings.push(error(format!(
+                "rule '{}': location {} is not a directory",
+                rule.name,
+                path.display()
+            )));
+        } else if changes_location && !is_writable(path) {
+            findings.push(error(format!(
+                "rule '{}': location {} is not writable, so files cannot be moved, renamed or deleted",
+                rule.name,
+                path.display()
+            )));
+        }
+    }
+    for action in &rule.actions {
+        let destination = match action {
+            Action::Move(destination) | Action::Copy(destination) => destination,
+            Action::Archive { destination, .. } => destination,
+            _ => continue,
+        };
+        if !destination.is_dir() {
+            findings.push(error(format!(
+                "rule '{}': destination {} is not an existing directory",
+                rule.name,
+                destination.display()
+            )));
+        } else if !is_writable(destination) {
+            findings.push(error(format!(
+                "rule '{}': destination {} is not writable",
+                rule.name,
+                destination.display()
+            )));
+        }
+    }
+}
+
+// A config shared between machines may name accounts only some of them have,
+// so these never match here rather than stopping the run
+fn check_accounts(rule: &Rule, findings: &mut Vec<Finding>) {
+    for filter in rule.filters.iter().flat_map(Filter::conditions) {
+        let (kind, accounts) = match filter {
+            Filter::Owner { owner } => ("user", owner),
+            Filter::Group { group } => ("group", group),
+            _ => continue,
+        };
+        for account in accounts.iter().filter(|account| account.id.is_none()) {
+            findings.push(warning(format!(
+                "rule '{}' filters on {} '{}', which does not exist on this system and never matches",
+                rule.name, kind, account.name
+            )));
+        }
+    }
+}
+
+// Filters are OR'd, so one broad filter is enough for a delete rule to take
+// files nobody meant it to
+fn check_deletes(rule: &Rule, findings: &mut Vec<Finding>) {
+    if rule.filters.is_empty() {
+        findings.push(warning(format!(
+            "rule '{}' has no filters and never matches",
+            rule.name
+        )));
+    }
+    if !rule
+        .actions
+        .iter()
+        .any(|action| matches!(action, Action::Delete))
+    {
+        return;
+    }
+    for (i, filter) in rule.filters.iter().enumerate() {
+        if !names_files(filter) {
+            findings.push(warning(format!(
+                "rule '{}' deletes every file filter {} (`{}`) matches, whatever its name or content",
+                rule.name,
+                i + 1,
+                filter
+            )));
+        }
+    }
+    let home = std::env::var_os("HOME");
+    for location in &rule.locations {
+        let path = location.path.as_path();
+        if path == Path::new("/") || home.as_deref().is_some_and(|home| path == Path::new(home)) {
+            findings.push(warning(format!(
+                "rule '{}' deletes files in {}",
+                rule.name,
+                path.display()
+            )));
+        }
+    }
+    if rule.targets.dirs() {
+        findings.push(warning(format!(
+            "rule '{}' deletes whole directories, along with everything in them",
+            rule.name
+        )));
+    }
+}
+
+// Filters that single out files by what they are called or contain
+fn names_files(filter: &Filter) -> bool {
+    filter.conditions().iter().any(|condition| {
+        matches!(
+            condition,
+            Filter::Extension { .. }
+                | Filter::NameContains { .. }
+                | Filter::XattrExists { .. }
+                | Filter::XattrEquals { .. }
+                | Filter::ContentContains { .. }
+                | Filter::ContentRegex { .. }
+        )
+    })
+}
+
+fn is_writable(path: &Path) -> bool {
+    let Ok(path) = CString::new(path.as_os_str().as_bytes()) else {
+        return false;
+    };
+    // SAFETY: path is a NUL-terminated string that outlives the call
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::{NamedTempFile, TempDir};
+
+    fn check_toml(toml_content: &str) -> Vec<String> {
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        check(temp_file.path(), Format::Toml)
+            .iter()
+            .map(|finding| finding.to_string())
+            .collect()
+    }
+
+    #[test]
+    fn test_check_reports_error_position() {
+        let findings = check_toml(
+            r#"
+[[rules]]
+name = "pdfs"
+locations = ["/tmp"]
+subfolders = "yes"
+filters = [{ extension = "pdf" }]
+actions = [{ echo = "pdf" }]
+"#,
+        );
+        assert_eq!(findings.len(), 1);
+        assert!(findings[0].starts_with("error: "), "{:?}", findings);
+        assert!(
+            findings[0].contains(":5:14: invalid type"),
+            "{:?}",
+            findings
+        );
+    }
+
+    #[test]
+    fn test_check_finds_misread_filters() {
+        let location = TempDir::new().unwrap();
+        let rules = format!(
+            r#"
+[[rules]]
+name = "rename vm"
+locations = ["{}"]
+subfolders = false
+filters = [{{ name = "vm" }}, {{ extension = "yaml", size_gt = 10 }}]
+actions = [{{ echo = "vm" }}]
+"#,
+            location.path().display()
+        );
+
+        let findings = check_toml(&format!("respect_gitignor = false\n{}", rules));
+        assert_eq!(findings.len(), 1, "{:?}", findings);
+        assert!(findings[0].starts_with("error: "));
+        assert!(
+            findings[0].contains(":7:14: unknown field `name`, expected one of `extension`"),
+            "{:?}",
+            findings
+        );
+
+        // the legacy schema reads both tables as before, and says what it drops
+        let findings = check_toml(&format!(
+            "respect_gitignor = false\nfilter_schema = \"legacy\"\n{}",
+            rules
+        ));
+        assert_eq!(
+            findings,
+            vec![
+                "warning: unknown key `respect_gitignor` is ignored",
+                "warning: rule 'rename vm', filter 1 never matches and is left out: the legacy schema reads `name` as an age filter without conditions",
+                "warning: rule 'rename vm', filter 2: `size_gt` ignored by the legacy schema, the strict one would require all of the table to match",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_paths_and_deletes() {
+        let location = TempDir::new().unwrap();
+        let missing = location.path().join("missing");
+        let findings = check_toml(&format!(
+            r#"
+[[rules]]
+name = "tidy"
+locations = ["{0}", "{1}"]
+subfolders = true
+filters = [{{ extension = "tmp" }}, {{ days_older_than = 30 }}]
+actions = ["delete"]
+
+[[rules]]
+name = "archive"
+locations = ["{0}"]
+subfolders = true
+filters = [{{ extension = "pdf" }}]
+actions = [{{ move = "{1}" }}]
+"#,
+            location.path().display(),
+            missing.display()
+        ));
+        assert_eq!(
+            findings,
+            vec![
+                format!("error: rule 'tidy': location {} does not exist", missing.display()),
+                "warning: rule 'tidy' deletes every file filter 2 (`days_older_than = 30`) matches, whatever its name or content".to_string(),
+                format!(
+                    "error: rule 'archive': destination {} is not an existing directory",
+                    missing.display()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_check_warns_about_unknown_accounts() {
+        let location = TempDir::new().unwrap();
+        let findings = check_toml(&format!(
+            r#"
+[[rules]]
+name = "uploads"
+locations = ["{}"]
+subfolders = false
+filters = [{{ owner = ["root", "no-such-user-sparkle"] }}, {{ group = "no-such-group-sparkle" }}]
+actions = [{{ echo = "upload" }}]
+"#,
+            location.path().display()
+        ));
+        assert_eq!(
+            findings,
+            vec![
+                "warning: rule 'uploads' filters on user 'no-such-user-sparkle', which does not exist on this system and never matches",
+                "warning: rule 'uploads' filters on group 'no-such-group-sparkle', which does not exist on this system and never matches",
+            ]
+        );
+    }
+}
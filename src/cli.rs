@@ -1,12 +1,72 @@
-use clap::Parser;
+use crate::format::Format;
+use clap::{Parser, Subcommand};
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Parser)]
 pub struct Cli {
     /// configuration file location
-    #[arg(long, short)]
-    pub configuration: String,
+    #[arg(long, short, global = true)]
+    pub configuration: Option<String>,
+
+    /// configuration language [default: by extension, .json, .yaml or .yml, otherwise toml]
+    #[arg(long, global = true)]
+    pub format: Option<Format>,
 
     /// verbosity
-    #[arg(long, short, default_value_t = false)]
+    #[arg(long, short, global = true, default_value_t = false)]
     pub verbose: bool,
+
+    /// where run state and lock files are kept [default: $XDG_STATE_HOME/sparkle]
+    #[arg(long, global = true)]
+    pub state_dir: Option<PathBuf>,
+
+    /// wait for another run of the same configuration to finish
+    #[arg(long, global = true, overrides_with = "no_wait")]
+    pub wait: bool,
+
+    /// skip this run if another one is in progress (the default)
+    #[arg(long, global = true, overrides_with = "wait")]
+    pub no_wait: bool,
+
+    /// without a command, every rule runs once
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Keep running and apply rules to files as they appear in rule locations
+    Watch {
+        /// how long a file must stay unchanged before rules run on it
+        #[arg(long, default_value = "2s", value_parser = humantime::parse_duration)]
+        settle: Duration,
+    },
+    /// Keep running and apply rules with a `schedule` or `every` on time
+    Daemon,
+    /// Report errors and risky rules in the configuration without running it
+    Check,
+    /// Work with configuration files
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Show how every rule's locations and filters treat a path, without running actions
+    Explain {
+        /// the file or directory to explain
+        path: PathBuf,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ConfigCommand {
+    /// Write the configuration in another format: toml, json or yaml
+    Convert {
+        /// format to write [default: by the extension of --output]
+        #[arg(long)]
+        to: Option<Format>,
+        /// file to write instead of standard output
+        #[arg(long, short)]
+        output: Option<PathBuf>,
+    },
 }
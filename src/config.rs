@@ -1,23 +1,359 @@
-use serde::Deserialize;
+use crate::file_metadata::{AgeCategory, FileType, SizeCategory};
+use crate::format::{Format, ParseError, Step, steps};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
+    #[serde(default)]
+    pub categories: Categories,
+    #[serde(default)]
+    pub file_types: FileTypes,
+    // gitignore-style patterns skipped by every rule
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    #[serde(default = "default_true")]
+    pub respect_gitignore: bool,
+    // what concurrent Sparkle processes must not run at the same time
+    #[serde(default)]
+    pub lock: LockScope,
+    // where locations with `cache = true` keep their cache, defaults to a
+    // `cache` directory in the state directory
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+    // whether a file handled by one rule is still checked against the next
+    #[serde(default)]
+    pub mode: Mode,
+    // how filter tables are read, see `FilterSchema`
+    #[serde(default)]
+    pub filter_schema: FilterSchema,
     pub rules: Vec<Rule>,
 }
 
+fn default_true() -> bool {
+    true
+}
+
+/// What a run locks against other Sparkle processes.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum LockScope {
+    // one run per configuration file at a time
+    #[default]
+    Config,
+    // one run per location, across all configuration files
+    Location,
+}
+
+/// How many rules may handle the same file. Rules are tried in the order
+/// they are written.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Mode {
+    // every matching rule, until one moves or deletes the file
+    #[default]
+    All,
+    // only the first matching rule
+    FirstMatch,
+}
+
+/// How the tables in `filters` are read.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterSchema {
+    // unknown keys are errors and all conditions of a table must match
+    #[default]
+    Strict,
+    // each table is one filter, as before the strict schema: keys of other
+    // filters are ignored, with a warning
+    Legacy,
+}
+
+/// Extension mappings from the `[file_types]` section, e.g.
+/// `code = ["py", "ts"]`. Names that are not a built-in `FileType` define a
+/// new custom category.
+#[derive(Debug, Default, Clone)]
+pub struct FileTypes {
+    by_extension: HashMap<String, FileType>,
+}
+
+impl FileTypes {
+    /// Looks up a lower-case extension without the leading dot.
+    pub fn get(&self, extension: &str) -> Option<&FileType> {
+        self.by_extension.get(extension)
+    }
+
+    /// True if `file_type` is built in or defined in this section.
+    pub fn is_known(&self, file_type: &FileType) -> bool {
+        !file_type.is_custom() || self.by_extension.values().any(|t| t == file_type)
+    }
+
+    /// Changes whenever a mapping does, so file types cached under other
+    /// mappings can be told apart.
+    pub fn fingerprint(&self) -> u64 {
+        let mut mappings: Vec<String> = self
+            .by_extension
+            .iter()
+            .map(|(ext, file_type)| format!("{}={}\n", ext, file_type.as_str()))
+            .collect();
+        mappings.sort();
+        crate::state::fnv1a(mappings.concat().as_bytes())
+    }
+}
+
+impl<'de> Deserialize<'de> for FileTypes {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let table = HashMap::<String, Vec<String>>::deserialize(deserializer)?;
+        let mut by_extension = HashMap::new();
+        for (name, extensions) in table {
+            let file_type = FileType::from(name);
+            for ext in extensions {
+                let ext = ext.trim_start_matches('.').to_lowercase();
+                if let Some(existing) = by_extension.insert(ext.clone(), file_type.clone())
+                    && existing != file_type
+                {
+                    return Err(serde::de::Error::custom(format!(
+                        "extension '{}' is mapped to both '{}' and '{}'",
+                        ext,
+                        existing.as_str(),
+                        file_type.as_str()
+                    )));
+                }
+            }
+        }
+        Ok(FileTypes { by_extension })
+    }
+}
+
+/// Thresholds for the size and age buckets computed for every file.
+#[derive(Deserialize, Debug, Default, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct Categories {
+    pub size: SizeThresholds,
+    pub age: AgeThresholds,
+}
+
+/// Exclusive upper bound of each size category in bytes. Anything at or above
+/// `large` is `huge`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct SizeThresholds {
+    pub tiny: u64,
+    pub small: u64,
+    pub medium: u64,
+    pub large: u64,
+}
+
+impl Default for SizeThresholds {
+    fn default() -> Self {
+        SizeThresholds {
+            tiny: 1024,
+            small: 1024 * 1024,
+            medium: 100 * 1024 * 1024,
+            large: 1024 * 1024 * 1024,
+        }
+    }
+}
+
+/// Exclusive upper bound of each age category in days since modification.
+/// Anything at or above `year` is `old`.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct AgeThresholds {
+    pub recent: u64,
+    pub week: u64,
+    pub month: u64,
+    pub year: u64,
+}
+
+impl Default for AgeThresholds {
+    fn default() -> Self {
+        AgeThresholds {
+            recent: 1,
+            week: 7,
+            month: 30,
+            year: 365,
+        }
+    }
+}
+
+impl Categories {
+    /// Each bound must be above the previous one, so the buckets are contiguous
+    /// and never overlap.
+    pub fn validate(&self) -> Result<(), String> {
+        let size = &self.size;
+        check_increasing(
+            "categories.size",
+            &[
+                ("tiny", size.tiny),
+                ("small", size.small),
+                ("medium", size.medium),
+                ("large", size.large),
+            ],
+        )?;
+        let age = &self.age;
+        check_increasing(
+            "categories.age",
+            &[
+                ("recent", age.recent),
+                ("week", age.week),
+                ("month", age.month),
+                ("year", age.year),
+            ],
+        )
+    }
+}
+
+fn check_increasing(section: &str, bounds: &[(&str, u64)]) -> Result<(), String> {
+    let mut previous: Option<(&str, u64)> = None;
+    for &(name, bound) in bounds {
+        if bound == 0 {
+            return Err(format!("{}.{} must be greater than 0", section, name));
+        }
+        if let Some((prev_name, prev_bound)) = previous
+            && bound <= prev_bound
+        {
+            return Err(format!(
+                "{}.{} ({}) must be greater than {}.{} ({}), otherwise the ranges overlap",
+                section, name, bound, section, prev_name, prev_bound
+            ));
+        }
+        previous = Some((name, bound));
+    }
+    Ok(())
+}
+
 #[derive(Deserialize, Debug)]
 pub struct Rule {
     pub name: String,
-    pub locations: Vec<PathBuf>,
+    pub locations: Vec<Location>,
     pub subfolders: bool,
+    #[serde(default)]
+    pub targets: Targets,
+    // descend into symlinked directories; loops are detected either way
+    #[serde(default)]
+    pub follow_symlinks: bool,
+    // stay on the device of the location
+    #[serde(default)]
+    pub same_filesystem: bool,
+    // filesystem types, as in /proc/self/mountinfo, not to descend into
+    #[serde(default)]
+    pub skip_fstypes: Vec<String>,
+    #[serde(default)]
+    pub exclude: Vec<String>,
+    // when `sparkle daemon` runs the rule: a cron expression or an interval
+    #[serde(default, deserialize_with = "deserialize_cron")]
+    pub schedule: Option<croner::Cron>,
+    #[serde(default, deserialize_with = "deserialize_optional_duration")]
+    pub every: Option<Duration>,
+    // files this rule handles are not checked against later rules
+    #[serde(default)]
+    pub stop: bool,
     pub filters: Vec<Filter>,
     pub actions: Vec<Action>,
 }
 
-#[derive(Deserialize, Debug)]
-pub struct CompressionFormat {}
+/// A directory searched by a rule, written either as a plain path or as a
+/// table with options, e.g. `{ path = "/home/user/Downloads", remove_empty_dirs = true }`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub path: PathBuf,
+    // prune directories that this run's actions left empty
+    pub remove_empty_dirs: bool,
+    // remember detected types between runs and skip detecting unchanged files
+    pub cache: bool,
+}
+
+impl From<PathBuf> for Location {
+    fn from(path: PathBuf) -> Self {
+        Location {
+            path,
+            remove_empty_dirs: false,
+            cache: false,
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Location {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(deny_unknown_fields)]
+        struct Options {
+            path: PathBuf,
+            #[serde(default)]
+            remove_empty_dirs: bool,
+            #[serde(default)]
+            cache: bool,
+        }
+
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum LocationInput {
+            Path(PathBuf),
+            Table(Options),
+        }
+
+        Ok(match LocationInput::deserialize(deserializer)? {
+            LocationInput::Path(path) => Location::from(path),
+            LocationInput::Table(options) => Location {
+                path: options.path,
+                remove_empty_dirs: options.remove_empty_dirs,
+                cache: options.cache,
+            },
+        })
+    }
+}
+
+/// Which entries of a location a rule is matched against. A directory that
+/// matches is handled as a whole, so nothing below it is visited.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Targets {
+    #[default]
+    Files,
+    Dirs,
+    Both,
+}
+
+impl Targets {
+    pub fn files(self) -> bool {
+        self != Targets::Dirs
+    }
+
+    pub fn dirs(self) -> bool {
+        self != Targets::Files
+    }
+}
+
+/// Container written by the archive action.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub enum CompressionFormat {
+    #[serde(rename = "tar")]
+    Tar,
+    #[default]
+    #[serde(rename = "tar.gz")]
+    TarGz,
+}
+
+impl CompressionFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            CompressionFormat::Tar => "tar",
+            CompressionFormat::TarGz => "tar.gz",
+        }
+    }
+}
 
 #[derive(Deserialize, Debug)]
 #[serde(rename_all = "snake_case")]
@@ -30,188 +366,2659 @@ pub enum Action {
         pattern: String,
         replacement: String,
     },
+    SetPermissions(u32),
+    TranscodeToUtf8,
+    SetXattr {
+        name: String,
+        value: String,
+    },
+    RemoveXattr(String),
+    Archive {
+        destination: PathBuf,
+        #[serde(default)]
+        format: CompressionFormat,
+    },
+}
+
+// The action as written in a configuration file, e.g. `"delete"` or
+// `move = "/backup"`
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let path = |path: &Path| format!("{:?}", path.display().to_string());
+        match self {
+            Action::Echo(message) => write!(f, "echo = {:?}", message),
+            Action::Move(destination) => write!(f, "move = {}", path(destination)),
+            Action::Copy(destination) => write!(f, "copy = {}", path(destination)),
+            Action::Delete => write!(f, "\"delete\""),
+            Action::Rename {
+                pattern,
+                replacement,
+            } => write!(
+                f,
+                "rename = {{ pattern = {:?}, replacement = {:?} }}",
+                pattern, replacement
+            ),
+            Action::SetPermissions(mode) => write!(f, "set_permissions = {}", mode),
+            Action::TranscodeToUtf8 => write!(f, "\"transcode_to_utf8\""),
+            Action::SetXattr { name, value } => {
+                write!(
+                    f,
+                    "set_xattr = {{ name = {:?}, value = {:?} }}",
+                    name, value
+                )
+            }
+            Action::RemoveXattr(name) => write!(f, "remove_xattr = {:?}", name),
+            Action::Archive {
+                destination,
+                format,
+            } => write!(
+                f,
+                "archive = {{ destination = {}, format = {:?} }}",
+                path(destination),
+                format.extension()
+            ),
+        }
+    }
+}
+
+/// One condition a path is checked against. In a configuration file each
+/// table in `filters` holds one or more conditions, e.g. `{ extension = "log" }`
+/// or `{ extension = "log", older_than = "30d" }`; see [`FilterTable`].
+#[derive(Debug)]
+pub enum Filter {
+    Extension {
+        extension: String,
+    },
+    NameContains {
+        name_contains: String,
+    },
+    Encoding {
+        encoding: String,
+    },
+    Language {
+        language: String,
+    },
+    FileType {
+        file_type: Vec<FileType>,
+    },
+    SizeCategory {
+        size_category: Vec<SizeCategory>,
+    },
+    AgeCategory {
+        age_category: Vec<AgeCategory>,
+    },
+    Owner {
+        owner: Vec<Account>,
+    },
+    Group {
+        group: Vec<Account>,
+    },
+    Uid {
+        uid: Vec<u32>,
+    },
+    Gid {
+        gid: Vec<u32>,
+    },
+    ModeHas {
+        mode_has: u32,
+    },
+    ModeLacks {
+        mode_lacks: u32,
+    },
+    XattrExists {
+        xattr_exists: String,
+    },
+    XattrMissing {
+        xattr_missing: String,
+    },
+    XattrEquals {
+        xattr_equals: XattrValue,
+    },
+    IsSymlink {
+        is_symlink: bool,
+    },
+    BrokenSymlink {
+        broken_symlink: bool,
+    },
+    // not in the location's cache, or changed since, see `Location::cache`
+    NewSinceLastRun {
+        new_since_last_run: bool,
+    },
+    // Directory filters, never true for files
+    IsEmpty {
+        is_empty: bool,
+    },
+    TotalSizeGt {
+        total_size_gt: u64,
+    },
+    FileCountGt {
+        file_count_gt: u64,
+    },
+    NewestFileOlderThan {
+        newest_file_older_than: Duration,
+    },
+    ContentContains {
+        content_contains: String,
+        max_bytes: Option<u64>,
+    },
+    ContentRegex {
+        content_regex: regex::bytes::Regex,
+        max_bytes: Option<u64>,
+    },
+    Age {
+        days_older_than: Option<u32>,
+        older_than: Option<Duration>,
+        newer_than: Option<Duration>,
+        before: Option<SystemTime>,
+        after: Option<SystemTime>,
+        timestamp: Timestamp,
+    },
+    Size {
+        size_gt: Option<u64>,
+        size_lt: Option<u64>,
+    },
+    // a table with several conditions, all of which must hold
+    All(Vec<Filter>),
+}
+
+/// A user or group an `owner` or `group` filter names, with its id on this
+/// system. Names are looked up once when the configuration is read; one that
+/// does not exist here has no id and never matches.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Account {
+    pub name: String,
+    pub id: Option<u32>,
+}
+
+/// A table in a rule's `filters` list. Every key belongs to exactly one
+/// filter and unknown keys are rejected. All conditions in one table must
+/// match, while the tables of a rule are alternatives.
+#[derive(Deserialize, Default)]
+#[serde(deny_unknown_fields)]
+struct FilterTable {
+    extension: Option<String>,
+    name_contains: Option<String>,
+    encoding: Option<String>,
+    language: Option<String>,
+    file_type: Option<OneOrMany<FileType>>,
+    size_category: Option<OneOrMany<SizeCategory>>,
+    age_category: Option<OneOrMany<AgeCategory>>,
+    owner: Option<OneOrMany<String>>,
+    group: Option<OneOrMany<String>>,
+    uid: Option<OneOrMany<u32>>,
+    gid: Option<OneOrMany<u32>>,
+    mode_has: Option<ModeBits>,
+    mode_lacks: Option<ModeBits>,
+    xattr_exists: Option<String>,
+    xattr_missing: Option<String>,
+    xattr_equals: Option<XattrValue>,
+    is_symlink: Option<bool>,
+    broken_symlink: Option<bool>,
+    new_since_last_run: Option<bool>,
+    is_empty: Option<bool>,
+    total_size_gt: Option<u64>,
+    file_count_gt: Option<u64>,
+    newest_file_older_than: Option<HumanDuration>,
+    content_contains: Option<String>,
+    content_regex: Option<Pattern>,
+    max_bytes: Option<u64>,
+    days_older_than: Option<u32>,
+    older_than: Option<HumanDuration>,
+    newer_than: Option<HumanDuration>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    before: Option<SystemTime>,
+    #[serde(default, deserialize_with = "deserialize_date")]
+    after: Option<SystemTime>,
+    timestamp: Option<Timestamp>,
+    size_gt: Option<u64>,
+    size_lt: Option<u64>,
+}
+
+impl FilterTable {
+    // Content filters come last so the cheap conditions can rule a file out
+    // before it is read
+    fn into_conditions(self) -> Result<Vec<Filter>, String> {
+        let mut conditions = Vec::new();
+        let mut push = |condition: Option<Filter>| conditions.extend(condition);
+        push(
+            self.extension
+                .map(|extension| Filter::Extension { extension }),
+        );
+        push(
+            self.name_contains
+                .map(|name_contains| Filter::NameContains { name_contains }),
+        );
+        push(self.encoding.map(|encoding| Filter::Encoding { encoding }));
+        push(self.language.map(|language| Filter::Language { language }));
+        push(self.file_type.map(|file_type| Filter::FileType {
+            file_type: file_type.into(),
+        }));
+        push(
+            self.size_category
+                .map(|size_category| Filter::SizeCategory {
+                    size_category: size_category.into(),
+                }),
+        );
+        push(self.age_category.map(|age_category| Filter::AgeCategory {
+            age_category: age_category.into(),
+        }));
+        let accounts = |names: OneOrMany<String>, lookup: fn(&str) -> Option<u32>| {
+            Vec::from(names)
+                .into_iter()
+                .map(|name| Account {
+                    id: lookup(&name),
+                    name,
+                })
+                .collect()
+        };
+        push(self.owner.map(|owner| Filter::Owner {
+            owner: accounts(owner, crate::accounts::uid_for_name),
+        }));
+        push(self.group.map(|group| Filter::Group {
+            group: accounts(group, crate::accounts::gid_for_name),
+        }));
+        push(self.uid.map(|uid| Filter::Uid { uid: uid.into() }));
+        push(self.gid.map(|gid| Filter::Gid { gid: gid.into() }));
+        push(
+            self.mode_has
+                .map(|mode| Filter::ModeHas { mode_has: mode.0 }),
+        );
+        push(
+            self.mode_lacks
+                .map(|mode| Filter::ModeLacks { mode_lacks: mode.0 }),
+        );
+        push(
+            self.xattr_exists
+                .map(|xattr_exists| Filter::XattrExists { xattr_exists }),
+        );
+        push(
+            self.xattr_missing
+                .map(|xattr_missing| Filter::XattrMissing { xattr_missing }),
+        );
+        push(
+            self.xattr_equals
+                .map(|xattr_equals| Filter::XattrEquals { xattr_equals }),
+        );
+        push(
+            self.is_symlink
+                .map(|is_symlink| Filter::IsSymlink { is_symlink }),
+        );
+        push(
+            self.broken_symlink
+                .map(|broken_symlink| Filter::BrokenSymlink { broken_symlink }),
+        );
+        push(
+            self.new_since_last_run
+                .map(|new_since_last_run| Filter::NewSinceLastRun { new_since_last_run }),
+        );
+        push(self.is_empty.map(|is_empty| Filter::IsEmpty { is_empty }));
+        push(
+            self.total_size_gt
+                .map(|total_size_gt| Filter::TotalSizeGt { total_size_gt }),
+        );
+        push(
+            self.file_count_gt
+                .map(|file_count_gt| Filter::FileCountGt { file_count_gt }),
+        );
+        push(
+            self.newest_file_older_than
+                .map(|duration| Filter::NewestFileOlderThan {
+                    newest_file_older_than: duration.0,
+                }),
+        );
+
+        let age = [
+            self.days_older_than.is_some(),
+            self.older_than.is_some(),
+            self.newer_than.is_some(),
+            self.before.is_some(),
+            self.after.is_some(),
+        ];
+        if age.contains(&true) {
+            push(Some(Filter::Age {
+                days_older_than: self.days_older_than,
+                older_than: self.older_than.map(|duration| duration.0),
+                newer_than: self.newer_than.map(|duration| duration.0),
+                before: self.before,
+                after: self.after,
+                timestamp: self.timestamp.unwrap_or_default(),
+            }));
+        } else if self.timestamp.is_some() {
+            return Err(
+                "timestamp needs one of days_older_than, older_than, newer_than, before or after"
+                    .to_string(),
+            );
+        }
+        if self.size_gt.is_some() || self.size_lt.is_some() {
+            push(Some(Filter::Size {
+                size_gt: self.size_gt,
+                size_lt: self.size_lt,
+            }));
+        }
+
+        let max_bytes = self.max_bytes;
+        if max_bytes.is_some() && self.content_contains.is_none() && self.content_regex.is_none() {
+            return Err("max_bytes needs content_contains or content_regex".to_string());
+        }
+        push(
+            self.content_contains
+                .map(|content_contains| Filter::ContentContains {
+                    content_contains,
+                    max_bytes,
+                }),
+        );
+        push(self.content_regex.map(|pattern| Filter::ContentRegex {
+            content_regex: pattern.0,
+            max_bytes,
+        }));
+        Ok(conditions)
+    }
+}
+
+// The filters the untagged enum before the strict schema tried, in order, with
+// the keys each of them read. A table without any of the first keys was read
+// as an age filter.
+const LEGACY_FILTERS: &[&[&str]] = &[
+    &["extension"],
+    &["name_contains"],
+    &["encoding"],
+    &["language"],
+    &["file_type"],
+    &["size_category"],
+    &["age_category"],
+    &["owner"],
+    &["group"],
+    &["uid"],
+    &["gid"],
+    &["mode_has"],
+    &["mode_lacks"],
+    &["xattr_exists"],
+    &["xattr_missing"],
+    &["xattr_equals"],
+    &["is_symlink"],
+    &["broken_symlink"],
+    &["new_since_last_run"],
+    &["is_empty"],
+    &["total_size_gt"],
+    &["file_count_gt"],
+    &["newest_file_older_than"],
+    &["content_contains", "max_bytes"],
+    &["content_regex", "max_bytes"],
+];
+const LEGACY_AGE: &[&str] = &[
+    "days_older_than",
+    "older_than",
+    "newer_than",
+    "before",
+    "after",
+    "timestamp",
+];
+
+// The keys of a filter table the untagged enum read, and the ones it ignored
+fn legacy_keys(table: &toml::Table) -> (&'static [&'static str], Vec<String>) {
+    let keys = LEGACY_FILTERS
+        .iter()
+        .find(|keys| table.contains_key(keys[0]))
+        .copied()
+        .unwrap_or(LEGACY_AGE);
+    let ignored = table
+        .keys()
+        .filter(|key| !keys.contains(&key.as_str()))
+        .map(|key| format!("`{}`", key))
+        .collect();
+    (keys, ignored)
+}
+
+fn rule_name(rule: &toml::Table) -> String {
+    rule.get("name")
+        .and_then(toml::Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+// Cuts every filter table down to the keys the untagged enum used, so the
+// strict schema reads it the same way. Age filters without a condition never
+// matched and are dropped. Also returns, for each rule, where each filter that
+// is left was written.
+fn read_legacy_filters(raw: &mut toml::Table) -> (Vec<String>, Vec<Vec<usize>>) {
+    let mut warnings = Vec::new();
+    let mut positions = Vec::new();
+    let Some(rules) = raw.get_mut("rules").and_then(toml::Value::as_array_mut) else {
+        return (warnings, positions);
+    };
+    for rule in rules.iter_mut() {
+        let mut kept = Vec::new();
+        if let Some(rule) = rule.as_table_mut() {
+            let name = rule_name(rule);
+            if let Some(filters) = rule.get_mut("filters").and_then(toml::Value::as_array_mut) {
+                let mut position = 0;
+                filters.retain_mut(|filter| {
+                    position += 1;
+                    let Some(table) = filter.as_table_mut() else {
+                        kept.push(position - 1);
+                        return true;
+                    };
+                    let (keys, ignored) = legacy_keys(table);
+                    table.retain(|key, _| keys.contains(&key));
+                    let never_matches = !table.keys().any(|key| key != "timestamp");
+                    if never_matches {
+                        warnings.push(format!(
+                            "rule '{}', filter {} never matches and is left out: the legacy schema reads {} as an age filter without conditions",
+                            name,
+                            position,
+                            ignored.join(", ")
+                        ));
+                    } else {
+                        if !ignored.is_empty() {
+                            warnings.push(format!(
+                                "rule '{}', filter {}: {} ignored by the legacy schema, the strict one would require all of the table to match",
+                                name,
+                                position,
+                                ignored.join(", ")
+                            ));
+                        }
+                        kept.push(position - 1);
+                    }
+                    !never_matches
+                });
+            }
+        }
+        positions.push(kept);
+    }
+    (warnings, positions)
+}
+
+// Filter tables the strict schema reads differently from the legacy one, for
+// configurations that do not name a schema
+fn ambiguous_filters(raw: &toml::Table) -> Vec<String> {
+    let mut warnings = Vec::new();
+    let rules = raw.get("rules").and_then(toml::Value::as_array);
+    for rule in rules
+        .into_iter()
+        .flatten()
+        .filter_map(toml::Value::as_table)
+    {
+        let filters = rule.get("filters").and_then(toml::Value::as_array);
+        for (position, table) in filters.into_iter().flatten().enumerate() {
+            let Some(table) = table.as_table() else {
+                continue;
+            };
+            let (keys, ignored) = legacy_keys(table);
+            if ignored.is_empty() {
+                continue;
+            }
+            let read: Vec<String> = table
+                .keys()
+                .filter(|key| keys.contains(&key.as_str()))
+                .map(|key| format!("`{}`", key))
+                .collect();
+            let before = if read.iter().any(|key| key != "`timestamp`") {
+                format!("earlier versions only read {}", read.join(", "))
+            } else {
+                "earlier versions read none of it and never matched".to_string()
+            };
+            warnings.push(format!(
+                "rule '{}', filter {} must match all of {}, {}; set filter_schema = \"strict\" to keep this or \"legacy\" for the old reading",
+                rule_name(rule),
+                position + 1,
+                table
+                    .keys()
+                    .map(|key| format!("`{}`", key))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                before
+            ));
+        }
+    }
+    warnings
+}
+
+// The path of a value in the text, from its path in the table the legacy
+// schema rewrote, whose filter lists have gaps
+fn written_path(path: &[Step], filter_positions: &[Vec<usize>]) -> Vec<Step> {
+    let mut path = path.to_vec();
+    if let [
+        Step::Key(rules),
+        Step::Index(rule),
+        Step::Key(filters),
+        Step::Index(filter),
+        ..,
+    ] = path.as_mut_slice()
+        && rules == "rules"
+        && filters == "filters"
+        && let Some(position) = filter_positions
+            .get(*rule)
+            .and_then(|positions| positions.get(*filter))
+    {
+        *filter = *position;
+    }
+    path
+}
+
+// Unknown filter keys are most likely filters written for the legacy schema
+fn legacy_hint(mut e: ParseError) -> ParseError {
+    let in_filter = e
+        .path
+        .windows(2)
+        .any(|steps| matches!(steps, [Step::Key(filters), Step::Index(_)] if filters == "filters"));
+    if in_filter && e.message.starts_with("unknown field") {
+        e.message.push_str(
+            "; filter_schema = \"legacy\" reads filter tables the way earlier versions did",
+        );
+    }
+    e
+}
+
+// The top-level keys that decide whether a configuration has to be rewritten
+// before it is deserialized
+#[derive(Deserialize)]
+struct Layout {
+    filter_schema: Option<FilterSchema>,
+    include: Option<IgnoredAny>,
+    defaults: Option<IgnoredAny>,
+    templates: Option<IgnoredAny>,
+}
+
+// A document a configuration was read from: the configuration itself, or a
+// file it includes
+struct Source {
+    file: Option<PathBuf>,
+    contents: String,
+    format: Format,
+}
+
+// A value in one of the sources
+#[derive(Clone)]
+struct Origin {
+    source: usize,
+    path: Vec<Step>,
+}
+
+impl Origin {
+    fn join(&self, steps: &[Step]) -> Origin {
+        let mut path = self.path.clone();
+        path.extend_from_slice(steps);
+        Origin {
+            source: self.source,
+            path,
+        }
+    }
+}
+
+// Where a rule of the merged configuration was written, and the table each
+// key it inherited comes from
+struct RuleOrigin {
+    table: Origin,
+    keys: HashMap<String, Origin>,
+}
+
+// Where the values of a configuration that is rewritten before it is
+// deserialized were written, so errors found afterwards point at the text
+struct Origins {
+    // the configuration first
+    sources: Vec<Source>,
+    rules: Vec<RuleOrigin>,
+}
+
+impl Origins {
+    fn new(contents: &str, format: Format) -> Origins {
+        Origins {
+            sources: vec![Source {
+                file: None,
+                contents: contents.to_string(),
+                format,
+            }],
+            rules: Vec::new(),
+        }
+    }
+
+    // Where the value at `path` in the merged configuration was written
+    fn of(&self, path: &[Step]) -> Origin {
+        if let [Step::Key(rules), Step::Index(rule), rest @ ..] = path
+            && rules == "rules"
+            && let Some(origin) = self.rules.get(*rule)
+        {
+            let table = match rest.first() {
+                Some(Step::Key(key)) => origin.keys.get(key).unwrap_or(&origin.table),
+                _ => &origin.table,
+            };
+            return table.join(rest);
+        }
+        Origin {
+            source: 0,
+            path: path.to_vec(),
+        }
+    }
+
+    fn error(&self, origin: Origin, message: String) -> ParseError {
+        let source = &self.sources[origin.source];
+        ParseError {
+            message,
+            position: source.format.position_of(&source.contents, &origin.path),
+            path: origin.path,
+            file: source.file.clone(),
+        }
+    }
+}
+
+fn key_at(source: usize, key: &str) -> Origin {
+    Origin {
+        source,
+        path: vec![Step::Key(key.to_string())],
+    }
+}
+
+// Appends the rules and templates of included files, then fills in every rule
+// from the template it extends and from `[defaults]`, in that order. A key the
+// rule sets itself replaces the inherited value as a whole, lists included.
+fn resolve_shared(
+    raw: &mut toml::Table,
+    origins: &mut Origins,
+    dir: &Path,
+) -> Result<(), ParseError> {
+    let mut templates = HashMap::new();
+    add_templates(raw.remove("templates"), 0, &mut templates, origins)?;
+    let mut included = Vec::new();
+    let files = included_files(raw.remove("include"), dir)
+        .map_err(|message| origins.error(key_at(0, "include"), message))?;
+    for path in files {
+        let format = Format::from_path(&path);
+        let read = fs::read_to_string(&path)
+            .map_err(|e| ParseError::new(e.to_string()))
+            .and_then(|contents| Ok((format.read(&contents)?, contents)));
+        let (mut file, contents) = read.map_err(|e| ParseError {
+            file: Some(path.clone()),
+            ..e
+        })?;
+        let source = origins.sources.len();
+        origins.sources.push(Source {
+            file: Some(path),
+            contents,
+            format,
+        });
+        if let Some(key) = file
+            .keys()
+            .find(|key| *key != "rules" && *key != "templates")
+        {
+            return Err(origins.error(
+                key_at(source, key),
+                format!(
+                    "an included file can only hold rules and templates, not {}",
+                    key
+                ),
+            ));
+        }
+        add_templates(file.remove("templates"), source, &mut templates, origins)?;
+        match file.remove("rules") {
+            Some(toml::Value::Array(rules)) => {
+                included.extend(rules.into_iter().enumerate().map(|(index, rule)| {
+                    (rule, key_at(source, "rules").join(&[Step::Index(index)]))
+                }))
+            }
+            Some(_) => {
+                return Err(origins.error(key_at(source, "rules"), "rules must be a list".into()));
+            }
+            None => {}
+        }
+    }
+    let defaults = match raw.remove("defaults") {
+        Some(toml::Value::Table(defaults)) => defaults,
+        Some(_) => {
+            return Err(origins.error(key_at(0, "defaults"), "defaults must be a table".into()));
+        }
+        None => toml::Table::new(),
+    };
+
+    let rules = raw
+        .entry("rules")
+        .or_insert_with(|| toml::Value::Array(Vec::new()));
+    // anything but a list is reported when the rules are deserialized
+    let Some(rules) = rules.as_array_mut() else {
+        return Ok(());
+    };
+    origins.rules = (0..rules.len())
+        .map(|index| RuleOrigin {
+            table: key_at(0, "rules").join(&[Step::Index(index)]),
+            keys: HashMap::new(),
+        })
+        .collect();
+    for (rule, table) in included {
+        rules.push(rule);
+        origins.rules.push(RuleOrigin {
+            table,
+            keys: HashMap::new(),
+        });
+    }
+    for (index, rule) in rules.iter_mut().enumerate() {
+        let Some(rule) = rule.as_table_mut() else {
+            continue;
+        };
+        let mut keys = HashMap::new();
+        let table = origins.rules[index].table.clone();
+        if let Err((origin, message)) = extend(rule, &table, &mut keys, &templates, &mut Vec::new())
+        {
+            let message = format!("rule '{}': {}", rule_name(rule), message);
+            return Err(origins.error(origin, message));
+        }
+        inherit(rule, &mut keys, &defaults, &key_at(0, "defaults"));
+        origins.rules[index].keys = keys;
+    }
+    Ok(())
+}
+
+// Adds the templates of one source to `templates`, with where they were written
+fn add_templates(
+    value: Option<toml::Value>,
+    source: usize,
+    templates: &mut HashMap<String, (toml::Table, Origin)>,
+    origins: &Origins,
+) -> Result<(), ParseError> {
+    let at = key_at(source, "templates");
+    let entries = match value {
+        None => return Ok(()),
+        Some(toml::Value::Table(entries)) => entries,
+        Some(_) => return Err(origins.error(at, "templates must be a table of rules".into())),
+    };
+    for (name, template) in entries {
+        let origin = at.join(&[Step::Key(name.clone())]);
+        let toml::Value::Table(template) = template else {
+            return Err(origins.error(origin, format!("template '{}' must be a table", name)));
+        };
+        if templates.contains_key(&name) {
+            return Err(origins.error(origin, format!("template '{}' is already defined", name)));
+        }
+        templates.insert(name, (template, origin));
+    }
+    Ok(())
 }
 
-#[derive(Deserialize, Debug)]
-#[serde(untagged)]
-pub enum Filter {
-    Extension {
-        extension: String,
-    },
-    NameContains {
-        name_contains: String,
-    },
-    Age {
-        days_older_than: Option<u32>,
-    },
-    Size {
-        size_gt: Option<u64>,
-        size_lt: Option<u64>,
-    },
-}
+// The files `include` names, relative to the directory of the configuration.
+// Patterns match in alphabetical order; a plain path must exist. A file named
+// by several patterns is read once.
+fn included_files(include: Option<toml::Value>, dir: &Path) -> Result<Vec<PathBuf>, String> {
+    let patterns = match include {
+        None => Vec::new(),
+        Some(toml::Value::String(pattern)) => vec![toml::Value::String(pattern)],
+        Some(toml::Value::Array(patterns)) => patterns,
+        Some(_) => return Err("include must be a list of paths".to_string()),
+    };
+    let mut files = Vec::new();
+    let mut seen = Vec::new();
+    for pattern in patterns {
+        let Some(pattern) = pattern.as_str() else {
+            return Err("include must be a list of paths".to_string());
+        };
+        let full = dir.join(pattern);
+        let matches = glob::glob(&full.to_string_lossy())
+            .map_err(|e| format!("include '{}': {}", pattern, e))?
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| format!("include '{}': {}", pattern, e))?;
+        if matches.is_empty() && glob::Pattern::escape(pattern) == pattern {
+            return Err(format!(
+                "include '{}': {} does not exist",
+                pattern,
+                full.display()
+            ));
+        }
+        for path in matches {
+            let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+            if !seen.contains(&canonical) {
+                seen.push(canonical);
+                files.push(path);
+            }
+        }
+    }
+    Ok(files)
+}
+
+// Fills in `table`, written at `origin`, from the template its `extends`
+// names, which may extend another one in turn. `keys` gets where each key it
+// inherits was written.
+fn extend(
+    table: &mut toml::Table,
+    origin: &Origin,
+    keys: &mut HashMap<String, Origin>,
+    templates: &HashMap<String, (toml::Table, Origin)>,
+    seen: &mut Vec<String>,
+) -> Result<(), (Origin, String)> {
+    let Some(extends) = table.remove("extends") else {
+        return Ok(());
+    };
+    let at = origin.join(&[Step::Key("extends".to_string())]);
+    let Some(name) = extends.as_str() else {
+        return Err((at, "extends must be the name of a template".to_string()));
+    };
+    if seen.iter().any(|other| other == name) {
+        return Err((at, format!("template '{}' extends itself", name)));
+    }
+    let Some((template, template_origin)) = templates.get(name) else {
+        return Err((
+            at,
+            format!("extends '{}', which is not in [templates]", name),
+        ));
+    };
+    let mut template = template.clone();
+    let mut template_keys = HashMap::new();
+    seen.push(name.to_string());
+    extend(
+        &mut template,
+        template_origin,
+        &mut template_keys,
+        templates,
+        seen,
+    )?;
+    for (key, value) in template {
+        if !table.contains_key(&key) {
+            let origin = template_keys
+                .remove(&key)
+                .unwrap_or_else(|| template_origin.clone());
+            keys.insert(key.clone(), origin);
+            table.insert(key, value);
+        }
+    }
+    Ok(())
+}
+
+fn inherit(
+    table: &mut toml::Table,
+    keys: &mut HashMap<String, Origin>,
+    from: &toml::Table,
+    origin: &Origin,
+) {
+    for (key, value) in from {
+        if !table.contains_key(key) {
+            table.insert(key.clone(), value.clone());
+            keys.insert(key.clone(), origin.clone());
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Filter {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let mut conditions = FilterTable::deserialize(deserializer)?
+            .into_conditions()
+            .map_err(serde::de::Error::custom)?;
+        match conditions.len() {
+            0 => Err(serde::de::Error::custom(
+                "a filter needs at least one condition",
+            )),
+            1 => Ok(conditions.remove(0)),
+            _ => Ok(Filter::All(conditions)),
+        }
+    }
+}
+
+/// Which of a file's timestamps an age filter looks at.
+#[derive(Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Timestamp {
+    #[default]
+    Modified,
+    Created,
+    Accessed,
+    // EXIF DateTimeOriginal of photos
+    Taken,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct XattrValue {
+    pub name: String,
+    pub value: String,
+}
+
+impl Filter {
+    /// Filters that have to open and read the file rather than look at its metadata.
+    pub fn reads_content(&self) -> bool {
+        self.reads_text()
+            || self.reads_taken()
+            || self.conditions().iter().any(|condition| {
+                matches!(
+                    condition,
+                    Filter::ContentContains { .. } | Filter::ContentRegex { .. }
+                )
+            })
+    }
+
+    /// Filters that need the encoding or language detected from the start of
+    /// the file.
+    pub fn reads_text(&self) -> bool {
+        self.conditions()
+            .iter()
+            .any(|condition| matches!(condition, Filter::Encoding { .. } | Filter::Language { .. }))
+    }
+
+    /// Filters that need the size and file count of a directory's contents.
+    pub fn reads_dir_stats(&self) -> bool {
+        self.conditions().iter().any(|condition| {
+            matches!(
+                condition,
+                Filter::IsEmpty { .. }
+                    | Filter::TotalSizeGt { .. }
+                    | Filter::FileCountGt { .. }
+                    | Filter::NewestFileOlderThan { .. }
+            )
+        })
+    }
+
+    /// Filters that need the time a photo was taken from its EXIF data.
+    pub fn reads_taken(&self) -> bool {
+        self.conditions().iter().any(|condition| {
+            matches!(
+                condition,
+                Filter::Age {
+                    timestamp: Timestamp::Taken,
+                    ..
+                }
+            )
+        })
+    }
+
+    /// The single conditions this filter is made of.
+    pub fn conditions(&self) -> &[Filter] {
+        match self {
+            Filter::All(conditions) => conditions,
+            condition => std::slice::from_ref(condition),
+        }
+    }
+}
+
+impl Timestamp {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Timestamp::Modified => "modified",
+            Timestamp::Created => "created",
+            Timestamp::Accessed => "accessed",
+            Timestamp::Taken => "taken",
+        }
+    }
+}
+
+// The keys and values that set the filter in a configuration file, e.g.
+// `extension = "pdf"` or `older_than = "30days", timestamp = "taken"`
+impl fmt::Display for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let text = |value: &str| format!("{:?}", value);
+        let list = |values: Vec<String>| match values.as_slice() {
+            [value] => value.clone(),
+            _ => format!("[{}]", values.join(", ")),
+        };
+        let duration =
+            |duration: &Duration| format!("\"{}\"", humantime::format_duration(*duration));
+        let date = |time: &SystemTime| {
+            let time = chrono::DateTime::<chrono::Local>::from(*time);
+            let format = if time.time() == chrono::NaiveTime::MIN {
+                "%Y-%m-%d"
+            } else {
+                "%Y-%m-%d %H:%M:%S"
+            };
+            format!("\"{}\"", time.format(format))
+        };
+        let mode = |bits: &u32| format!("\"{:04o}\"", bits);
+        let keys: Vec<(&str, Option<String>)> = match self {
+            Filter::Extension { extension } => vec![("extension", Some(text(extension)))],
+            Filter::NameContains { name_contains } => {
+                vec![("name_contains", Some(text(name_contains)))]
+            }
+            Filter::Encoding { encoding } => vec![("encoding", Some(text(encoding)))],
+            Filter::Language { language } => vec![("language", Some(text(language)))],
+            Filter::FileType { file_type } => vec![(
+                "file_type",
+                Some(list(file_type.iter().map(|t| text(t.as_str())).collect())),
+            )],
+            Filter::SizeCategory { size_category } => vec![(
+                "size_category",
+                Some(list(
+                    size_category.iter().map(|c| text(c.as_str())).collect(),
+                )),
+            )],
+            Filter::AgeCategory { age_category } => vec![(
+                "age_category",
+                Some(list(
+                    age_category.iter().map(|c| text(c.as_str())).collect(),
+                )),
+            )],
+            Filter::Owner { owner } => vec![(
+                "owner",
+                Some(list(
+                    owner.iter().map(|account| text(&account.name)).collect(),
+                )),
+            )],
+            Filter::Group { group } => vec![(
+                "group",
+                Some(list(
+                    group.iter().map(|account| text(&account.name)).collect(),
+                )),
+            )],
+            Filter::Uid { uid } => {
+                vec![("uid", Some(list(uid.iter().map(u32::to_string).collect())))]
+            }
+            Filter::Gid { gid } => {
+                vec![("gid", Some(list(gid.iter().map(u32::to_string).collect())))]
+            }
+            Filter::ModeHas { mode_has } => vec![("mode_has", Some(mode(mode_has)))],
+            Filter::ModeLacks { mode_lacks } => vec![("mode_lacks", Some(mode(mode_lacks)))],
+            Filter::XattrExists { xattr_exists } => {
+                vec![("xattr_exists", Some(text(xattr_exists)))]
+            }
+            Filter::XattrMissing { xattr_missing } => {
+                vec![("xattr_missing", Some(text(xattr_missing)))]
+            }
+            Filter::XattrEquals { xattr_equals } => vec![(
+                "xattr_equals",
+                Some(format!(
+                    "{{ name = {}, value = {} }}",
+                    text(&xattr_equals.name),
+                    text(&xattr_equals.value)
+                )),
+            )],
+            Filter::IsSymlink { is_symlink } => vec![("is_symlink", Some(is_symlink.to_string()))],
+            Filter::BrokenSymlink { broken_symlink } => {
+                vec![("broken_symlink", Some(broken_symlink.to_string()))]
+            }
+            Filter::NewSinceLastRun { new_since_last_run } => {
+                vec![("new_since_last_run", Some(new_since_last_run.to_string()))]
+            }
+            Filter::IsEmpty { is_empty } => vec![("is_empty", Some(is_empty.to_string()))],
+            Filter::TotalSizeGt { total_size_gt } => {
+                vec![("total_size_gt", Some(total_size_gt.to_string()))]
+            }
+            Filter::FileCountGt { file_count_gt } => {
+                vec![("file_count_gt", Some(file_count_gt.to_string()))]
+            }
+            Filter::NewestFileOlderThan {
+                newest_file_older_than,
+            } => vec![(
+                "newest_file_older_than",
+                Some(duration(newest_file_older_than)),
+            )],
+            Filter::ContentContains {
+                content_contains,
+                max_bytes,
+            } => vec![
+                ("content_contains", Some(text(content_contains))),
+                ("max_bytes", max_bytes.map(|max| max.to_string())),
+            ],
+            Filter::ContentRegex {
+                content_regex,
+                max_bytes,
+            } => vec![
+                ("content_regex", Some(text(content_regex.as_str()))),
+                ("max_bytes", max_bytes.map(|max| max.to_string())),
+            ],
+            Filter::Age {
+                days_older_than,
+                older_than,
+                newer_than,
+                before,
+                after,
+                timestamp,
+            } => vec![
+                (
+                    "days_older_than",
+                    days_older_than.map(|days| days.to_string()),
+                ),
+                ("older_than", older_than.as_ref().map(duration)),
+                ("newer_than", newer_than.as_ref().map(duration)),
+                ("before", before.as_ref().map(date)),
+                ("after", after.as_ref().map(date)),
+                (
+                    "timestamp",
+                    (*timestamp != Timestamp::Modified).then(|| text(timestamp.as_str())),
+                ),
+            ],
+            Filter::Size { size_gt, size_lt } => vec![
+                ("size_gt", size_gt.map(|size| size.to_string())),
+                ("size_lt", size_lt.map(|size| size.to_string())),
+            ],
+            Filter::All(conditions) => {
+                let conditions: Vec<String> = conditions
+                    .iter()
+                    .map(|condition| condition.to_string())
+                    .collect();
+                return write!(f, "{}", conditions.join(", "));
+            }
+        };
+        let keys: Vec<String> = keys
+            .into_iter()
+            .filter_map(|(key, value)| Some(format!("{} = {}", key, value?)))
+            .collect();
+        write!(f, "{}", keys.join(", "))
+    }
+}
+
+#[derive(Deserialize)]
+struct Pattern(#[serde(deserialize_with = "deserialize_regex")] regex::bytes::Regex);
+
+fn deserialize_regex<'de, D>(deserializer: D) -> Result<regex::bytes::Regex, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let pattern = String::deserialize(deserializer)?;
+    regex::bytes::Regex::new(&pattern).map_err(serde::de::Error::custom)
+}
+
+#[derive(Deserialize)]
+struct ModeBits(#[serde(deserialize_with = "deserialize_mode")] u32);
+
+// Permission bits, either octal like set_permissions (2, "0002", "4000") or
+// symbolic ("o+w", "ug+x", "u+s")
+fn deserialize_mode<'de, D>(deserializer: D) -> Result<u32, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum ModeInput {
+        Number(u32),
+        Text(String),
+    }
+
+    let text = match ModeInput::deserialize(deserializer)? {
+        ModeInput::Number(number) => number.to_string(),
+        ModeInput::Text(text) => text,
+    };
+    parse_mode_bits(&text).map_err(serde::de::Error::custom)
+}
+
+/// Parses permission bits written in octal ("0755") or chmod's symbolic form
+/// ("o+w", "ug+rx,o+t"). Only `+` is accepted: the bits are checked one by
+/// one, so chmod's exact `=` has no meaning here.
+pub fn parse_mode_bits(text: &str) -> Result<u32, String> {
+    let invalid = || format!("invalid mode '{}', expected e.g. \"0002\" or \"o+w\"", text);
+    if !text.is_empty() && text.chars().all(|c| c.is_digit(8)) {
+        return u32::from_str_radix(text, 8)
+            .ok()
+            .filter(|bits| *bits <= 0o7777)
+            .ok_or_else(invalid);
+    }
+
+    let mut bits = 0;
+    for clause in text.split(',') {
+        if clause.contains('=') {
+            return Err(format!(
+                "invalid mode '{}', `=` would mean an exact mode; use mode_has and mode_lacks with `+`",
+                text
+            ));
+        }
+        let (who, perms) = clause.split_once('+').unwrap_or(("", clause));
+        let who = if who.is_empty() { "a" } else { who };
+        if perms.is_empty() || !who.chars().all(|c| "ugoa".contains(c)) {
+            return Err(invalid());
+        }
+        for class in who.chars() {
+            let classes: &[char] = if class == 'a' {
+                &['u', 'g', 'o']
+            } else {
+                &[class]
+            };
+            for class in classes {
+                for perm in perms.chars() {
+                    // others have no set-id bit, which `a+s` leaves out
+                    // like chmod does
+                    if *class == 'o' && perm == 's' && classes.len() > 1 {
+                        continue;
+                    }
+                    bits |= match (class, perm) {
+                        ('u', 'r') => 0o400,
+                        ('u', 'w') => 0o200,
+                        ('u', 'x') => 0o100,
+                        ('g', 'r') => 0o040,
+                        ('g', 'w') => 0o020,
+                        ('g', 'x') => 0o010,
+                        ('o', 'r') => 0o004,
+                        ('o', 'w') => 0o002,
+                        ('o', 'x') => 0o001,
+                        ('u', 's') => 0o4000,
+                        ('g', 's') => 0o2000,
+                        (_, 't') => 0o1000,
+                        _ => return Err(invalid()),
+                    };
+                }
+            }
+        }
+    }
+    Ok(bits)
+}
+
+// Standard five field cron expressions such as "0 3 * * *"
+fn deserialize_cron<'de, D>(deserializer: D) -> Result<Option<croner::Cron>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(text) => text
+            .parse()
+            .map(Some)
+            .map_err(|e| serde::de::Error::custom(format!("invalid schedule '{}': {}", text, e))),
+        None => Ok(None),
+    }
+}
+
+// Human readable durations such as "36h", "2w" or "1d 12h"
+fn deserialize_duration<'de, D>(deserializer: D) -> Result<Duration, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let text = String::deserialize(deserializer)?;
+    humantime::parse_duration(&text)
+        .map_err(|e| serde::de::Error::custom(format!("invalid duration '{}': {}", text, e)))
+}
+
+#[derive(Deserialize)]
+struct HumanDuration(#[serde(deserialize_with = "deserialize_duration")] Duration);
+
+fn deserialize_optional_duration<'de, D>(deserializer: D) -> Result<Option<Duration>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Ok(Option::<HumanDuration>::deserialize(deserializer)?.map(|duration| duration.0))
+}
+
+// Accepts quoted strings as well as native TOML dates, e.g. `before = 2024-01-31`
+fn deserialize_date<'de, D>(deserializer: D) -> Result<Option<SystemTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum DateInput {
+        Text(String),
+        Toml(toml::value::Datetime),
+    }
+
+    let text = match Option::<DateInput>::deserialize(deserializer)? {
+        Some(DateInput::Text(text)) => text,
+        Some(DateInput::Toml(datetime)) => datetime.to_string(),
+        None => return Ok(None),
+    };
+    parse_date(&text)
+        .map(Some)
+        .map_err(serde::de::Error::custom)
+}
+
+/// Parses an RFC 3339 timestamp, or a date with an optional time of day in the
+/// local time zone ("2024-01-31", "2024-01-31 18:30", "2024-01-31T18:30:00").
+pub fn parse_date(text: &str) -> Result<SystemTime, String> {
+    use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, TimeZone};
+
+    if let Ok(datetime) = DateTime::parse_from_rfc3339(text) {
+        return Ok(datetime.into());
+    }
+    let naive = [
+        "%Y-%m-%d %H:%M:%S",
+        "%Y-%m-%dT%H:%M:%S",
+        "%Y-%m-%d %H:%M",
+        "%Y-%m-%dT%H:%M",
+    ]
+    .iter()
+    .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+    .or_else(|| {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+    })
+    .ok_or_else(|| format!("invalid date '{}', expected e.g. 2024-01-31", text))?;
+    Local
+        .from_local_datetime(&naive)
+        .earliest()
+        .map(SystemTime::from)
+        .ok_or_else(|| format!("'{}' does not exist in the local time zone", text))
+}
+
+// Accepts either a single value or a list, e.g. `file_type = "image"` or
+// `file_type = ["image", "video"]`
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Self {
+        match value {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+impl Config {
+    // Actions run on wherever the previous one left the file, so nothing can
+    // come after one that gets rid of it
+    fn validate_actions(&self) -> Result<(), String> {
+        for rule in &self.rules {
+            let Some(position) = rule
+                .actions
+                .iter()
+                .position(|action| matches!(action, Action::Delete))
+            else {
+                continue;
+            };
+            if let Some(next) = rule.actions.get(position + 1) {
+                return Err(format!(
+                    "rule '{}' runs {:?} after delete, delete must be the last action",
+                    rule.name, next
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // Without a cache every file would count as new on every run
+    fn validate_caches(&self) -> Result<(), String> {
+        for rule in &self.rules {
+            if !rule
+                .filters
+                .iter()
+                .flat_map(Filter::conditions)
+                .any(|filter| matches!(filter, Filter::NewSinceLastRun { .. }))
+            {
+                continue;
+            }
+            if let Some(location) = rule.locations.iter().find(|location| !location.cache) {
+                return Err(format!(
+                    "rule '{}' filters on new_since_last_run, which needs cache = true on location {}",
+                    rule.name,
+                    location.path.display()
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    // The daemon keeps last runs and caches by rule name
+    fn validate_names(&self) -> Result<(), String> {
+        let mut names = HashSet::new();
+        for rule in &self.rules {
+            if !names.insert(rule.name.as_str()) {
+                return Err(format!(
+                    "two rules are named '{}', rule names must be unique",
+                    rule.name
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_schedules(&self) -> Result<(), String> {
+        for rule in &self.rules {
+            if rule.schedule.is_some() && rule.every.is_some() {
+                return Err(format!(
+                    "rule '{}' sets both schedule and every, pick one",
+                    rule.name
+                ));
+            }
+            if rule.every.is_some_and(|every| every.is_zero()) {
+                return Err(format!("rule '{}': every must be longer than 0", rule.name));
+            }
+        }
+        Ok(())
+    }
+
+    fn validate_excludes(&self) -> Result<(), String> {
+        crate::exclude::validate_patterns(&self.exclude)?;
+        for rule in &self.rules {
+            crate::exclude::validate_patterns(&rule.exclude)
+                .map_err(|e| format!("rule '{}': {}", rule.name, e))?;
+        }
+        Ok(())
+    }
+
+    // A file_type filter naming a category nobody defined would never match
+    fn validate_file_types(&self) -> Result<(), String> {
+        for rule in &self.rules {
+            for filter in rule.filters.iter().flat_map(Filter::conditions) {
+                if let Filter::FileType { file_type } = filter
+                    && let Some(unknown) = file_type.iter().find(|t| !self.file_types.is_known(t))
+                {
+                    return Err(format!(
+                        "rule '{}' filters on file_type '{}', which is not a built-in type or defined in [file_types]",
+                        rule.name,
+                        unknown.as_str()
+                    ));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Loads and validates the configuration at `path`, in the format its
+    /// extension names.
+    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = Path::new(path);
+        Config::load(path, Format::from_path(path))
+    }
+
+    pub fn load(path: &Path, format: Format) -> Result<Self, Box<dyn std::error::Error>> {
+        let contents = fs::read_to_string(path)?;
+        let dir = path.parent().unwrap_or(Path::new(""));
+        let (config, warnings) = Config::parse(&contents, format, dir, |_| {})?;
+        for warning in warnings {
+            tracing::warn!("{}", warning);
+        }
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Deserializes a configuration without validating it. `ignored` gets
+    /// every key that is not part of the format, e.g. `rules.0.subfolder`.
+    /// With `filter_schema = "legacy"` the warnings list the filter keys that
+    /// are ignored the way earlier versions did. Files in `include` are
+    /// relative to `dir`.
+    pub fn parse(
+        contents: &str,
+        format: Format,
+        dir: &Path,
+        mut ignored: impl FnMut(String),
+    ) -> Result<(Config, Vec<String>), ParseError> {
+        let mut on_ignored = |path: serde_ignored::Path| ignored(path.to_string());
+        let layout: Layout = format.deserialize(contents, |_| {})?;
+        let legacy = layout.filter_schema == Some(FilterSchema::Legacy);
+        let shared =
+            layout.include.is_some() || layout.defaults.is_some() || layout.templates.is_some();
+        if !legacy && !shared {
+            let config = format
+                .deserialize(contents, &mut on_ignored)
+                .map_err(legacy_hint)?;
+            let warnings = match layout.filter_schema {
+                Some(_) => Vec::new(),
+                None => format
+                    .read(contents)
+                    .map(|raw| ambiguous_filters(&raw))
+                    .unwrap_or_default(),
+            };
+            return Ok((config, warnings));
+        }
+        let mut raw = format.read(contents)?;
+        let mut origins = Origins::new(contents, format);
+        if shared {
+            resolve_shared(&mut raw, &mut origins, dir)?;
+        }
+        let (warnings, filter_positions) = if legacy {
+            read_legacy_filters(&mut raw)
+        } else if layout.filter_schema.is_none() {
+            (ambiguous_filters(&raw), Vec::new())
+        } else {
+            (Vec::new(), Vec::new())
+        };
+        let mut track = serde_path_to_error::Track::new();
+        let config = Config::deserialize(serde_path_to_error::Deserializer::new(
+            serde_ignored::Deserializer::new(toml::Value::Table(raw), &mut on_ignored),
+            &mut track,
+        ))
+        .map_err(|e| {
+            let path = written_path(&steps(&track.path()), &filter_positions);
+            let e = origins.error(origins.of(&path), e.message().trim().to_string());
+            if legacy { e } else { legacy_hint(e) }
+        })?;
+        Ok((config, warnings))
+    }
+
+    /// Checks what deserializing alone cannot, e.g. that categories do not
+    /// overlap and that rule names are unique.
+    pub fn validate(&self) -> Result<(), String> {
+        self.categories.validate()?;
+        self.validate_names()?;
+        self.validate_file_types()?;
+        self.validate_excludes()?;
+        self.validate_schedules()?;
+        self.validate_caches()?;
+        self.validate_actions()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_config_new_valid_toml() {
+        let toml_content = r#"
+[[rules]]
+name = "test_rule"
+locations = ["/tmp"]
+subfolders = true
+filters = [
+    { extension = "txt" }
+]
+actions = [
+    { echo = "Found file" }
+]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(config.rules.len(), 1);
+        assert_eq!(config.rules[0].name, "test_rule");
+        assert_eq!(config.rules[0].locations.len(), 1);
+        assert!(config.rules[0].subfolders);
+        assert_eq!(config.rules[0].filters.len(), 1);
+        assert_eq!(config.rules[0].actions.len(), 1);
+    }
+
+    #[test]
+    fn test_config_new_invalid_toml() {
+        let invalid_toml = "invalid toml content [[[";
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", invalid_toml).unwrap();
+
+        let result = Config::new(temp_file.path().to_str().unwrap());
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_config_new_nonexistent_file() {
+        let result = Config::new("/nonexistent/path/config.toml");
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_config_new_empty_rules() {
+        let toml_content = r#"
+rules = []
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(config.rules.len(), 0);
+    }
+
+    #[test]
+    fn test_filter_variants() {
+        let toml_content = r#"
+[[rules]]
+name = "multi_filter_rule"
+locations = ["/tmp"]
+subfolders = false
+filters = [
+    { extension = "log" },
+    { size_gt = 1024, size_lt = 1048576 },
+    { days_older_than = 30 },
+    { name_contains = "backup" }
+]
+actions = [
+    { echo = "Processing" }
+]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(config.rules[0].filters.len(), 4);
+
+        match &config.rules[0].filters[0] {
+            Filter::Extension { extension } => assert_eq!(extension, "log"),
+            _ => panic!("Expected Extension filter"),
+        }
+
+        match &config.rules[0].filters[3] {
+            Filter::NameContains { name_contains } => assert_eq!(name_contains, "backup"),
+            _ => panic!("Expected NameContains filter"),
+        }
+    }
+
+    #[test]
+    fn test_action_variants() {
+        let toml_content = r#"
+[[rules]]
+name = "action_test"
+locations = ["/tmp"]
+subfolders = false
+filters = [{ extension = "tmp" }]
+actions = [
+    { echo = "Test message" },
+    { move = "/archive" },
+    { copy = "/backup" },
+    { rename = { pattern = "old", replacement = "new" } },
+    { set_permissions = 644 },
+    "delete"
+]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(config.rules[0].actions.len(), 6);
+
+        match &config.rules[0].actions[0] {
+            Action::Echo(msg) => assert_eq!(msg, "Test message"),
+            _ => panic!("Expected Echo action"),
+        }
+    }
+    #[test]
+    fn test_name_contains() {
+        let toml_content = r#"
+[[rules]]
+name = "action_test"
+locations = ["/tmp"]
+subfolders = false
+filters = [{ name_contains = "tmp" }]
+actions = [
+    { echo = "Test message" },
+    { move = "/archive" },
+    { copy = "/backup" },
+    { rename = { pattern = "old", replacement = "new" } },
+    { set_permissions = 644 },
+    "delete"
+]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(config.rules[0].actions.len(), 6);
+
+        match &config.rules[0].actions[0] {
+            Action::Echo(msg) => assert_eq!(msg, "Test message"),
+            _ => panic!("Expected Echo action"),
+        }
+    }
+
+    #[test]
+    fn test_text_content_filters() {
+        let toml_content = r#"
+[[rules]]
+name = "legacy_text"
+locations = ["/tmp"]
+subfolders = false
+filters = [
+    { encoding = "windows-1252" },
+    { language = "deu" }
+]
+actions = ["transcode_to_utf8"]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        match &config.rules[0].filters[0] {
+            Filter::Encoding { encoding } => assert_eq!(encoding, "windows-1252"),
+            _ => panic!("Expected Encoding filter"),
+        }
+        match &config.rules[0].filters[1] {
+            Filter::Language { language } => assert_eq!(language, "deu"),
+            _ => panic!("Expected Language filter"),
+        }
+        assert!(matches!(
+            config.rules[0].actions[0],
+            Action::TranscodeToUtf8
+        ));
+    }
+
+    #[test]
+    fn test_content_filters() {
+        let toml_content = r#"
+[[rules]]
+name = "invoices"
+locations = ["/tmp"]
+subfolders = false
+filters = [
+    { content_contains = "INVOICE", max_bytes = 4096 },
+    { content_regex = "order #[0-9]+" }
+]
+actions = [{ echo = "Found invoice" }]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        match &config.rules[0].filters[0] {
+            Filter::ContentContains {
+                content_contains,
+                max_bytes,
+            } => {
+                assert_eq!(content_contains, "INVOICE");
+                assert_eq!(*max_bytes, Some(4096));
+            }
+            _ => panic!("Expected ContentContains filter"),
+        }
+        match &config.rules[0].filters[1] {
+            Filter::ContentRegex { content_regex, .. } => {
+                assert!(content_regex.is_match(b"order #42"))
+            }
+            _ => panic!("Expected ContentRegex filter"),
+        }
+        assert!(config.rules[0].filters.iter().all(Filter::reads_content));
+    }
+
+    #[test]
+    fn test_filters_written_back() {
+        let config: Config = toml::from_str(
+            r#"
+[[rules]]
+name = "written"
+locations = ["/tmp"]
+subfolders = false
+filters = [
+    { extension = "pdf" },
+    { file_type = ["video", "audio"], size_gt = 1000 },
+    { older_than = "30d", timestamp = "taken" },
+    { mode_has = "o+w" },
+    { before = "2024-01-31" }
+]
+actions = [{ echo = "written" }]
+"#,
+        )
+        .unwrap();
+        let written: Vec<String> = config.rules[0]
+            .filters
+            .iter()
+            .map(|filter| filter.to_string())
+            .collect();
+        assert_eq!(
+            written,
+            [
+                r#"extension = "pdf""#,
+                r#"file_type = ["video", "audio"], size_gt = 1000"#,
+                r#"older_than = "30days", timestamp = "taken""#,
+                r#"mode_has = "0002""#,
+                r#"before = "2024-01-31""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_rule_names_are_unique() {
+        let rule = r#"
+[[rules]]
+name = "tidy"
+locations = ["/tmp"]
+subfolders = false
+filters = [{ extension = "tmp" }]
+actions = [{ echo = "tmp" }]
+"#;
+        let config: Config = toml::from_str(&rule.repeat(2)).unwrap();
+        assert_eq!(
+            config.validate().unwrap_err(),
+            "two rules are named 'tidy', rule names must be unique"
+        );
+    }
+
+    #[test]
+    fn test_category_filters() {
+        let toml_content = r#"
+[[rules]]
+name = "big_media"
+locations = ["/tmp"]
+subfolders = false
+filters = [
+    { file_type = "image" },
+    { file_type = ["video", "audio"] },
+    { size_category = ["large", "huge"] },
+    { age_category = "old" }
+]
+actions = [{ echo = "Found media" }]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+        let filters = &config.rules[0].filters;
+
+        match &filters[0] {
+            Filter::FileType { file_type } => assert_eq!(file_type, &vec![FileType::Image]),
+            _ => panic!("Expected FileType filter"),
+        }
+        match &filters[1] {
+            Filter::FileType { file_type } => {
+                assert_eq!(file_type, &vec![FileType::Video, FileType::Audio])
+            }
+            _ => panic!("Expected FileType filter"),
+        }
+        match &filters[2] {
+            Filter::SizeCategory { size_category } => {
+                assert_eq!(
+                    size_category,
+                    &vec![SizeCategory::Large, SizeCategory::Huge]
+                )
+            }
+            _ => panic!("Expected SizeCategory filter"),
+        }
+        match &filters[3] {
+            Filter::AgeCategory { age_category } => {
+                assert_eq!(age_category, &vec![AgeCategory::Old])
+            }
+            _ => panic!("Expected AgeCategory filter"),
+        }
+    }
+
+    #[test]
+    fn test_categories_default() {
+        let toml_content = r#"
+rules = []
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(config.categories.size.tiny, 1024);
+        assert_eq!(config.categories.size.large, 1_073_741_824);
+        assert_eq!(config.categories.age.recent, 1);
+        assert_eq!(config.categories.age.year, 365);
+    }
+
+    #[test]
+    fn test_categories_partial_override() {
+        let toml_content = r#"
+rules = []
+
+[categories.size]
+large = 10_737_418_240
+
+[categories.age]
+month = 60
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(config.categories.size.medium, 104_857_600);
+        assert_eq!(config.categories.size.large, 10_737_418_240);
+        assert_eq!(config.categories.age.month, 60);
+        assert_eq!(config.categories.age.week, 7);
+    }
+
+    #[test]
+    fn test_categories_overlapping_ranges() {
+        let toml_content = r#"
+rules = []
+
+[categories.age]
+week = 7
+month = 5
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let err = Config::new(temp_file.path().to_str().unwrap()).unwrap_err();
+
+        assert!(err.to_string().contains("categories.age.month"));
+    }
+
+    #[test]
+    fn test_categories_unknown_key() {
+        let toml_content = r#"
+rules = []
+
+[categories.size]
+enormous = 5
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+
+        assert!(Config::new(temp_file.path().to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_custom_file_types() {
+        let toml_content = r#"
+[file_types]
+code = ["py", "ts"]
+ebook = ["epub", ".MOBI"]
+
+[[rules]]
+name = "books"
+locations = ["/tmp"]
+subfolders = false
+filters = [{ file_type = "ebook" }]
+actions = [{ echo = "Found book" }]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(config.file_types.get("py"), Some(&FileType::Code));
+        assert_eq!(
+            config.file_types.get("mobi"),
+            Some(&FileType::Custom("ebook".to_string()))
+        );
+        match &config.rules[0].filters[0] {
+            Filter::FileType { file_type } => {
+                assert_eq!(file_type, &vec![FileType::Custom("ebook".to_string())])
+            }
+            _ => panic!("Expected FileType filter"),
+        }
+    }
+
+    #[test]
+    fn test_undefined_custom_file_type() {
+        let toml_content = r#"
+[[rules]]
+name = "typo"
+locations = ["/tmp"]
+subfolders = false
+filters = [{ file_type = "imgae" }]
+actions = [{ echo = "never" }]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let err = Config::new(temp_file.path().to_str().unwrap()).unwrap_err();
 
-impl Config {
-    pub fn new(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        let contents = fs::read_to_string(path)?;
-        let config: Config = toml::from_str(&contents)?;
-        Ok(config)
+        assert!(err.to_string().contains("imgae"));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
+    #[test]
+    fn test_conflicting_file_types() {
+        let toml_content = r#"
+rules = []
+
+[file_types]
+code = ["ts"]
+video = ["ts"]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+
+        assert!(Config::new(temp_file.path().to_str().unwrap()).is_err());
+    }
 
     #[test]
-    fn test_config_new_valid_toml() {
+    fn test_xattr_filters_and_actions() {
         let toml_content = r#"
 [[rules]]
-name = "test_rule"
+name = "tag_downloads"
 locations = ["/tmp"]
-subfolders = true
+subfolders = false
 filters = [
-    { extension = "txt" }
+    { xattr_missing = "user.sparkle.rule" },
+    { xattr_exists = "user.xdg.origin.url" },
+    { xattr_equals = { name = "user.sparkle.rule", value = "old" } }
 ]
 actions = [
-    { echo = "Found file" }
+    { set_xattr = { name = "user.sparkle.rule", value = "tag_downloads" } },
+    { remove_xattr = "user.xdg.origin.url" }
 ]
 "#;
 
         let mut temp_file = NamedTempFile::new().unwrap();
         write!(temp_file, "{}", toml_content).unwrap();
         let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+        let rule = &config.rules[0];
 
-        assert_eq!(config.rules.len(), 1);
-        assert_eq!(config.rules[0].name, "test_rule");
-        assert_eq!(config.rules[0].locations.len(), 1);
-        assert!(config.rules[0].subfolders);
-        assert_eq!(config.rules[0].filters.len(), 1);
-        assert_eq!(config.rules[0].actions.len(), 1);
+        assert!(matches!(&rule.filters[0], Filter::XattrMissing { .. }));
+        assert!(matches!(&rule.filters[1], Filter::XattrExists { .. }));
+        match &rule.filters[2] {
+            Filter::XattrEquals { xattr_equals } => {
+                assert_eq!(xattr_equals.name, "user.sparkle.rule");
+                assert_eq!(xattr_equals.value, "old");
+            }
+            _ => panic!("Expected XattrEquals filter"),
+        }
+        match &rule.actions[0] {
+            Action::SetXattr { name, value } => {
+                assert_eq!(name, "user.sparkle.rule");
+                assert_eq!(value, "tag_downloads");
+            }
+            _ => panic!("Expected SetXattr action"),
+        }
+        assert!(
+            matches!(&rule.actions[1], Action::RemoveXattr(name) if name == "user.xdg.origin.url")
+        );
     }
 
     #[test]
-    fn test_config_new_invalid_toml() {
-        let invalid_toml = "invalid toml content [[[";
+    fn test_age_filter_durations_and_dates() {
+        let toml_content = r#"
+[[rules]]
+name = "ages"
+locations = ["/tmp"]
+subfolders = false
+filters = [
+    { older_than = "2w", newer_than = "36h" },
+    { before = "2024-01-31T12:00:00Z", after = 2023-01-01, timestamp = "accessed" },
+    { days_older_than = 30 }
+]
+actions = [{ echo = "Found file" }]
+"#;
 
         let mut temp_file = NamedTempFile::new().unwrap();
-        write!(temp_file, "{}", invalid_toml).unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+        let filters = &config.rules[0].filters;
 
-        let result = Config::new(temp_file.path().to_str().unwrap());
-        assert!(result.is_err());
+        match &filters[0] {
+            Filter::Age {
+                older_than,
+                newer_than,
+                timestamp,
+                ..
+            } => {
+                assert_eq!(*older_than, Some(Duration::from_secs(14 * 86_400)));
+                assert_eq!(*newer_than, Some(Duration::from_secs(36 * 3600)));
+                assert_eq!(*timestamp, Timestamp::Modified);
+            }
+            _ => panic!("Expected Age filter"),
+        }
+        match &filters[1] {
+            Filter::Age {
+                before,
+                after,
+                timestamp,
+                ..
+            } => {
+                let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(1_706_702_400);
+                assert_eq!(*before, Some(expected));
+                assert!(after.is_some());
+                assert_eq!(*timestamp, Timestamp::Accessed);
+            }
+            _ => panic!("Expected Age filter"),
+        }
+        match &filters[2] {
+            Filter::Age {
+                days_older_than, ..
+            } => assert_eq!(*days_older_than, Some(30)),
+            _ => panic!("Expected Age filter"),
+        }
     }
 
     #[test]
-    fn test_config_new_nonexistent_file() {
-        let result = Config::new("/nonexistent/path/config.toml");
-        assert!(result.is_err());
+    fn test_parse_date() {
+        assert!(parse_date("2024-01-31").is_ok());
+        assert!(parse_date("2024-01-31 18:30").is_ok());
+        assert!(parse_date("2024-01-31T18:30:00+02:00").is_ok());
+        assert!(parse_date("31/01/2024").is_err());
+        assert!(parse_date("2024-02-30").is_err());
     }
 
     #[test]
-    fn test_config_new_empty_rules() {
+    fn test_owner_and_mode_filters() {
         let toml_content = r#"
-rules = []
+[[rules]]
+name = "uploads"
+locations = ["/tmp"]
+subfolders = false
+filters = [
+    { owner = "root" },
+    { group = ["root"] },
+    { uid = [0, 33] },
+    { gid = 0 },
+    { mode_has = "o+w" },
+    { mode_lacks = "0600" }
+]
+actions = [{ echo = "Found upload" }]
 "#;
 
         let mut temp_file = NamedTempFile::new().unwrap();
         write!(temp_file, "{}", toml_content).unwrap();
         let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+        let filters = &config.rules[0].filters;
 
-        assert_eq!(config.rules.len(), 0);
+        let root = vec![Account {
+            name: "root".to_string(),
+            id: Some(0),
+        }];
+        assert!(matches!(&filters[0], Filter::Owner { owner } if owner == &root));
+        assert!(matches!(&filters[1], Filter::Group { group } if group == &root));
+        assert!(matches!(&filters[2], Filter::Uid { uid } if uid == &[0, 33]));
+        assert!(matches!(&filters[3], Filter::Gid { gid } if gid == &[0]));
+        assert!(matches!(filters[4], Filter::ModeHas { mode_has: 0o002 }));
+        assert!(matches!(
+            filters[5],
+            Filter::ModeLacks { mode_lacks: 0o600 }
+        ));
     }
 
     #[test]
-    fn test_filter_variants() {
+    fn test_unknown_owner() {
         let toml_content = r#"
 [[rules]]
-name = "multi_filter_rule"
+name = "uploads"
+locations = ["/tmp"]
+subfolders = false
+filters = [{ owner = ["no-such-user-sparkle", "root"] }]
+actions = [{ echo = "never" }]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        // another machine may have the user, so the config still loads
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        match &config.rules[0].filters[0] {
+            Filter::Owner { owner } => {
+                assert_eq!(owner[0].id, None);
+                assert_eq!(owner[1].id, Some(0));
+            }
+            _ => panic!("Expected Owner filter"),
+        }
+    }
+
+    #[test]
+    fn test_parse_mode_bits() {
+        assert_eq!(parse_mode_bits("0002"), Ok(0o002));
+        assert_eq!(parse_mode_bits("4755"), Ok(0o4755));
+        assert_eq!(parse_mode_bits("o+w"), Ok(0o002));
+        assert_eq!(parse_mode_bits("ug+rx"), Ok(0o550));
+        assert_eq!(parse_mode_bits("a+r,u+s"), Ok(0o4444));
+        assert_eq!(parse_mode_bits("+t"), Ok(0o1000));
+        assert!(parse_mode_bits("o+q").is_err());
+        assert_eq!(parse_mode_bits("a+s"), Ok(0o6000));
+        assert_eq!(parse_mode_bits("+s"), Ok(0o6000));
+        assert!(parse_mode_bits("o+s").is_err());
+        assert!(parse_mode_bits("o=w").is_err());
+        assert!(parse_mode_bits("u+r,o=").is_err());
+        assert!(parse_mode_bits("77777").is_err());
+        assert!(parse_mode_bits("").is_err());
+    }
+
+    #[test]
+    fn test_exclude_lists() {
+        let toml_content = r#"
+exclude = [".cache", "*.part"]
+
+[[rules]]
+name = "projects"
+locations = ["/tmp"]
+subfolders = true
+exclude = ["node_modules", "target/"]
+filters = [{ extension = "log" }]
+actions = [{ echo = "Found log" }]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        assert_eq!(config.exclude, vec![".cache", "*.part"]);
+        assert!(config.respect_gitignore);
+        assert_eq!(config.rules[0].exclude, vec!["node_modules", "target/"]);
+    }
+
+    #[test]
+    fn test_directory_rule() {
+        let toml_content = r#"
+[[rules]]
+name = "abandoned projects"
 locations = ["/tmp"]
 subfolders = false
+targets = "dirs"
 filters = [
-    { extension = "log" },
-    { size_gt = 1024, size_lt = 1048576 },
-    { days_older_than = 30 },
-    { name_contains = "backup" }
-]
-actions = [
-    { echo = "Processing" }
+    { is_empty = true },
+    { newest_file_older_than = "180d" },
+    { total_size_gt = 1000 },
+    { file_count_gt = 10 },
 ]
+actions = [{ archive = { destination = "/backups" } }, "delete"]
 "#;
 
         let mut temp_file = NamedTempFile::new().unwrap();
         write!(temp_file, "{}", toml_content).unwrap();
         let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
 
-        assert_eq!(config.rules[0].filters.len(), 4);
+        let rule = &config.rules[0];
+        assert_eq!(rule.targets, Targets::Dirs);
+        assert!(rule.filters.iter().all(Filter::reads_dir_stats));
+        match &rule.filters[1] {
+            Filter::NewestFileOlderThan {
+                newest_file_older_than,
+            } => assert_eq!(*newest_file_older_than, Duration::from_secs(180 * 86400)),
+            other => panic!("Expected NewestFileOlderThan filter, got {:?}", other),
+        }
+        match &rule.actions[0] {
+            Action::Archive {
+                destination,
+                format,
+            } => {
+                assert_eq!(destination, &PathBuf::from("/backups"));
+                assert_eq!(*format, CompressionFormat::TarGz);
+            }
+            other => panic!("Expected Archive action, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_location_options() {
+        let toml_content = r#"
+[[rules]]
+name = "downloads"
+locations = ["/tmp", { path = "/var/tmp", remove_empty_dirs = true }]
+subfolders = true
+filters = [{ extension = "pdf" }]
+actions = [{ move = "/tmp/pdfs" }]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        let locations = &config.rules[0].locations;
+        assert_eq!(locations[0], Location::from(PathBuf::from("/tmp")));
+        assert_eq!(locations[1].path, PathBuf::from("/var/tmp"));
+        assert!(locations[1].remove_empty_dirs);
+        assert!(!locations[1].cache);
+    }
+
+    #[test]
+    fn test_mode_and_stop() {
+        let toml_content = r#"
+mode = "first_match"
+
+[[rules]]
+name = "invoices"
+locations = ["/tmp"]
+subfolders = false
+stop = true
+filters = [{ name_contains = "invoice" }]
+actions = [{ move = "/tmp/invoices" }]
+
+[[rules]]
+name = "pdfs"
+locations = ["/tmp"]
+subfolders = false
+filters = [{ extension = "pdf" }]
+actions = [{ move = "/tmp/pdfs" }]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+        assert_eq!(config.mode, Mode::FirstMatch);
+        assert!(config.rules[0].stop);
+        assert!(!config.rules[1].stop);
+
+        let config: Config = toml::from_str("rules = []").unwrap();
+        assert_eq!(config.mode, Mode::All);
+    }
+
+    #[test]
+    fn test_strict_filter_tables() {
+        let read_schema = |schema: &str, filters: &str| {
+            let toml_content = format!(
+                r#"
+filter_schema = "{}"
+
+[[rules]]
+name = "r"
+locations = ["/tmp"]
+subfolders = false
+filters = [{}]
+actions = ["delete"]
+"#,
+                schema, filters
+            );
+            Config::parse(&toml_content, Format::Toml, Path::new(""), |_| {})
+        };
+        let read = |filters: &str| read_schema("strict", filters);
 
+        let (config, warnings) = read(r#"{ extension = "log", older_than = "30d" }"#).unwrap();
+        assert!(warnings.is_empty());
         match &config.rules[0].filters[0] {
-            Filter::Extension { extension } => assert_eq!(extension, "log"),
-            _ => panic!("Expected Extension filter"),
+            Filter::All(conditions) => {
+                assert!(
+                    matches!(&conditions[0], Filter::Extension { extension } if extension == "log")
+                );
+                assert!(matches!(
+                    conditions[1],
+                    Filter::Age {
+                        older_than: Some(_),
+                        ..
+                    }
+                ));
+            }
+            _ => panic!("Expected conditions combined into one filter"),
         }
+        let (config, _) = read("{ size_gt = 1000 }").unwrap();
+        assert!(matches!(
+            config.rules[0].filters[0],
+            Filter::Size {
+                size_gt: Some(1000),
+                size_lt: None
+            }
+        ));
 
-        match &config.rules[0].filters[3] {
-            Filter::NameContains { name_contains } => assert_eq!(name_contains, "backup"),
-            _ => panic!("Expected NameContains filter"),
+        let unknown = read("{ size_greater = 1000 }").err().unwrap().to_string();
+        assert!(
+            unknown.contains("unknown field `size_greater`"),
+            "{}",
+            unknown
+        );
+        for (filters, error) in [
+            ("{}", "at least one condition"),
+            (r#"{ timestamp = "created" }"#, "timestamp needs one of"),
+            ("{ max_bytes = 10 }", "max_bytes needs content_contains"),
+        ] {
+            let message = read(filters).err().unwrap().to_string();
+            assert!(message.contains(error), "{}", message);
         }
+
+        // the legacy schema keeps only the keys the untagged enum read
+        let (config, warnings) = read_schema(
+            "legacy",
+            r#"{ name = "vm" }, { extension = "log", days_older_than = 30 }"#,
+        )
+        .unwrap();
+        assert_eq!(config.filter_schema, FilterSchema::Legacy);
+        assert_eq!(config.rules[0].filters.len(), 1);
+        assert!(matches!(
+            &config.rules[0].filters[0],
+            Filter::Extension { .. }
+        ));
+        assert_eq!(warnings.len(), 2);
+
+        // errors after a left out filter point at the filter as written
+        let e = read_schema("legacy", r#"{ name = "vm" }, { extension = 5 }"#)
+            .err()
+            .unwrap();
+        assert_eq!(e.position, Some((8, 43)), "{}", e);
+        assert!(e.message.contains("invalid type: integer `5`"), "{}", e);
     }
 
     #[test]
-    fn test_action_variants() {
+    fn test_ambiguous_filter_tables_warn() {
+        let read = |schema: &str| {
+            let toml_content = format!(
+                r#"{}
+[[rules]]
+name = "logs"
+locations = ["/tmp"]
+subfolders = false
+filters = [{{ extension = "log", days_older_than = 30 }}, {{ extension = "tmp" }}]
+actions = ["delete"]
+"#,
+                schema
+            );
+            Config::parse(&toml_content, Format::Toml, Path::new(""), |_| {})
+        };
+
+        let (config, warnings) = read("").unwrap();
+        assert!(matches!(config.rules[0].filters[0], Filter::All(_)));
+        assert_eq!(
+            warnings,
+            [
+                "rule 'logs', filter 1 must match all of `extension`, `days_older_than`, earlier versions only read `extension`; set filter_schema = \"strict\" to keep this or \"legacy\" for the old reading"
+            ]
+        );
+        let (_, warnings) = read("filter_schema = \"strict\"").unwrap();
+        assert!(warnings.is_empty());
+
+        let toml_content = "[[rules]]\nname = \"r\"\nlocations = []\nsubfolders = false\nfilters = [{ name = \"backup\" }]\nactions = []\n";
+        let e = Config::parse(toml_content, Format::Toml, Path::new(""), |_| {})
+            .err()
+            .unwrap();
+        assert_eq!(e.position, Some((5, 14)));
+        assert!(
+            e.message.ends_with(
+                "filter_schema = \"legacy\" reads filter tables the way earlier versions did"
+            ),
+            "{}",
+            e
+        );
+    }
+
+    #[test]
+    fn test_nothing_runs_after_delete() {
         let toml_content = r#"
 [[rules]]
-name = "action_test"
+name = "cleanup"
 locations = ["/tmp"]
 subfolders = false
-filters = [{ extension = "tmp" }]
-actions = [
-    { echo = "Test message" },
-    { move = "/archive" },
-    { copy = "/backup" },
-    "delete",
-    { rename = { pattern = "old", replacement = "new" } },
-    { set_permissions = 644 }
-]
+filters = [{ extension = "log" }]
+actions = [{ copy = "/tmp/backup" }, "delete", { echo = "gone" }]
 "#;
 
         let mut temp_file = NamedTempFile::new().unwrap();
         write!(temp_file, "{}", toml_content).unwrap();
-        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+        let err = Config::new(temp_file.path().to_str().unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(
+            err.contains("rule 'cleanup' runs Echo(\"gone\") after delete"),
+            "{}",
+            err
+        );
+    }
 
-        assert_eq!(config.rules[0].actions.len(), 6);
+    #[test]
+    fn test_new_since_last_run_needs_cache() {
+        let toml_content = r#"
+[[rules]]
+name = "new downloads"
+locations = [{ path = "/tmp", cache = true }, "/var/tmp"]
+subfolders = true
+filters = [{ new_since_last_run = true }]
+actions = [{ echo = "new" }]
+"#;
 
-        match &config.rules[0].actions[0] {
-            Action::Echo(msg) => assert_eq!(msg, "Test message"),
-            _ => panic!("Expected Echo action"),
-        }
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let err = Config::new(temp_file.path().to_str().unwrap())
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("cache = true on location /var/tmp"), "{}", err);
+
+        let toml_content =
+            toml_content.replace(r#""/var/tmp""#, r#"{ path = "/var/tmp", cache = true }"#);
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+        assert!(config.rules[0].locations.iter().all(|l| l.cache));
+        assert!(matches!(
+            config.rules[0].filters[0],
+            Filter::NewSinceLastRun {
+                new_since_last_run: true
+            }
+        ));
     }
+
     #[test]
-    fn test_name_contains() {
+    fn test_rule_schedules() {
         let toml_content = r#"
 [[rules]]
-name = "action_test"
+name = "nightly"
 locations = ["/tmp"]
 subfolders = false
-filters = [{ name_contains = "tmp" }]
-actions = [
-    { echo = "Test message" },
-    { move = "/archive" },
-    { copy = "/backup" },
-    "delete",
-    { rename = { pattern = "old", replacement = "new" } },
-    { set_permissions = 644 }
-]
+schedule = "0 3 * * *"
+filters = [{ extension = "log" }]
+actions = ["delete"]
+
+[[rules]]
+name = "frequent"
+locations = ["/tmp"]
+subfolders = false
+every = "15m"
+filters = [{ extension = "pdf" }]
+actions = [{ echo = "Found pdf" }]
 "#;
 
         let mut temp_file = NamedTempFile::new().unwrap();
         write!(temp_file, "{}", toml_content).unwrap();
         let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
 
-        assert_eq!(config.rules[0].actions.len(), 6);
+        assert!(config.rules[0].schedule.is_some());
+        assert_eq!(config.rules[0].every, None);
+        assert_eq!(config.rules[1].every, Some(Duration::from_secs(15 * 60)));
 
-        match &config.rules[0].actions[0] {
-            Action::Echo(msg) => assert_eq!(msg, "Test message"),
-            _ => panic!("Expected Echo action"),
+        for schedule in [
+            r#"schedule = "0 3 * *""#,
+            r#"schedule = "0 3 * * *"
+every = "1h""#,
+            r#"every = "0s""#,
+        ] {
+            let toml_content = format!(
+                r#"
+[[rules]]
+name = "bad"
+locations = ["/tmp"]
+subfolders = false
+{}
+filters = [{{ extension = "log" }}]
+actions = ["delete"]
+"#,
+                schedule
+            );
+            let mut temp_file = NamedTempFile::new().unwrap();
+            write!(temp_file, "{}", toml_content).unwrap();
+            assert!(
+                Config::new(temp_file.path().to_str().unwrap()).is_err(),
+                "{}",
+                schedule
+            );
         }
     }
+
+    #[test]
+    fn test_invalid_exclude_pattern() {
+        let toml_content = r#"
+[[rules]]
+name = "bad_glob"
+locations = ["/tmp"]
+subfolders = true
+exclude = ["[z-a]"]
+filters = [{ extension = "log" }]
+actions = [{ echo = "never" }]
+"#;
+
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let err = Config::new(temp_file.path().to_str().unwrap()).unwrap_err();
+
+        assert!(err.to_string().contains("bad_glob"));
+    }
+
+    #[test]
+    fn test_include_defaults_and_templates() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        fs::create_dir(dir.join("rules.d")).unwrap();
+        fs::write(
+            dir.join("rules.d").join("b.toml"),
+            r#"
+[[rules]]
+name = "logs"
+extends = "downloads"
+filters = [{ extension = "log" }]
+actions = ["delete"]
+"#,
+        )
+        .unwrap();
+        fs::write(
+            dir.join("rules.d").join("a.json"),
+            r#"{ "templates": { "downloads": { "extends": "home", "locations": ["/tmp/downloads"] } } }"#,
+        )
+        .unwrap();
+        let toml_content = r#"
+include = ["rules.d/*"]
+
+[defaults]
+subfolders = false
+exclude = ["*.part"]
+
+[templates.home]
+locations = ["/home"]
+actions = [{ echo = "home" }]
+
+[[rules]]
+name = "pdfs"
+extends = "home"
+subfolders = true
+filters = [{ extension = "pdf" }]
+"#;
+        let (config, _) = Config::parse(toml_content, Format::Toml, dir, |_| {}).unwrap();
+        let names: Vec<&str> = config.rules.iter().map(|rule| rule.name.as_str()).collect();
+        assert_eq!(names, ["pdfs", "logs"]);
+
+        let pdfs = &config.rules[0];
+        assert_eq!(pdfs.locations[0].path, PathBuf::from("/home"));
+        assert!(pdfs.subfolders);
+        assert_eq!(pdfs.exclude, ["*.part"]);
+        assert!(matches!(&pdfs.actions[..], [Action::Echo(_)]));
+
+        // the rule's own actions replace the template's, and templates extend
+        // others across files
+        let logs = &config.rules[1];
+        assert_eq!(logs.locations[0].path, PathBuf::from("/tmp/downloads"));
+        assert!(!logs.subfolders);
+        assert!(matches!(&logs.actions[..], [Action::Delete]));
+
+        // a file two patterns name is read once
+        let (config, _) = Config::parse(
+            &toml_content.replace(
+                r#"include = ["rules.d/*"]"#,
+                r#"include = ["rules.d/*", "rules.d/b.toml"]"#,
+            ),
+            Format::Toml,
+            dir,
+            |_| {},
+        )
+        .unwrap();
+        assert_eq!(config.rules.len(), 2);
+
+        let error = |toml_content: &str| {
+            Config::parse(toml_content, Format::Toml, dir, |_| {})
+                .err()
+                .unwrap()
+        };
+        let rule = "[[rules]]\nname = \"r\"\nextends = \"a\"\n";
+        let e = error(&format!("[templates]\n{}", rule));
+        assert_eq!(
+            e.message,
+            "rule 'r': extends 'a', which is not in [templates]"
+        );
+        assert_eq!(e.position, Some((4, 11)));
+        let e = error(&format!(
+            "[templates.a]\nextends = \"b\"\n[templates.b]\nextends = \"a\"\n{}",
+            rule
+        ));
+        assert_eq!(e.message, "rule 'r': template 'a' extends itself");
+        assert_eq!(e.position, Some((4, 11)));
+        assert!(
+            error("include = [\"missing.toml\"]\nrules = []")
+                .message
+                .ends_with("missing.toml does not exist")
+        );
+
+        // errors point at where the value was written, also when inherited
+        let e = error(&toml_content.replace("subfolders = false", "subfolders = \"no\""));
+        assert_eq!(e.file, None);
+        assert_eq!(e.position, Some((5, 14)), "{}", e);
+        assert!(
+            e.message.starts_with("invalid type: string \"no\""),
+            "{}",
+            e
+        );
+        let b = dir.join("rules.d").join("b.toml");
+        fs::write(
+            &b,
+            fs::read_to_string(&b).unwrap() + "subfolders = \"yes\"\n",
+        )
+        .unwrap();
+        let e = error(toml_content);
+        assert_eq!(e.file, Some(b.clone()));
+        assert_eq!(e.position, Some((7, 14)), "{}", e);
+        assert!(
+            e.to_string()
+                .starts_with(&format!("{}: line 7, column 14: ", b.display()))
+        );
+
+        fs::write(dir.join("rules.d").join("c.toml"), "mode = \"first_match\"").unwrap();
+        let e = error("include = [\"rules.d/*.toml\"]\nrules = []");
+        assert_eq!(e.file, Some(dir.join("rules.d").join("c.toml")));
+        assert_eq!(e.position, Some((1, 8)));
+        assert_eq!(
+            e.message,
+            "an included file can only hold rules and templates, not mode"
+        );
+    }
 }
@@ -1,25 +1,33 @@
+use crate::file_metadata::FileType;
+use crate::media_detector::read_media_info;
+use crate::text_detector::{
+    SAMPLE_SIZE, detect_encoding, detect_language, encoding_name, read_sample,
+};
+use std::path::Path;
 use std::time::Duration;
 use std::time::SystemTime;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct ContentInfo {
     // MIME type detection
     pub mime_type: String,    // "image/jpeg", "text/plain", etc.
     pub mime_confidence: f32, // How confident the detection is
 
     // Content-specific metadata
+    pub media_detected: bool, // whether detect_media ran
     pub media_info: Option<MediaInfo>,
     pub document_info: Option<DocumentInfo>,
     pub archive_info: Option<ArchiveInfo>,
 
     // Content analysis
+    pub text_detected: bool,           // whether detect_text ran
     pub text_encoding: Option<String>, // UTF-8, ASCII, etc.
     pub language: Option<String>,      // For text files
     pub hash: Option<String>,          // SHA-256 for deduplication
     pub has_metadata: bool,            // EXIF, ID3 ta
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct MediaInfo {
     pub width: Option<u32>,
     pub height: Option<u32>,
@@ -30,7 +38,7 @@ pub struct MediaInfo {
     pub gps_coordinates: Option<(f64, f64)>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct DocumentInfo {
     pub page_count: Option<u32>,
     pub word_count: Option<u32>,
@@ -39,10 +47,59 @@ pub struct DocumentInfo {
     pub created_date: Option<SystemTime>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ArchiveInfo {
     pub format: String, // "zip", "tar.gz", etc.
     pub file_count: u32,
     pub uncompressed_size: u64,
     pub compression_ratio: f32,
 }
+
+impl ContentInfo {
+    /// What the file name alone tells, without opening the file.
+    pub fn from_name(path: &Path) -> ContentInfo {
+        let mime = mime_guess2::from_path(path);
+        ContentInfo {
+            mime_type: mime.first_or_octet_stream().to_string(),
+            mime_confidence: if mime.is_empty() { 0.0 } else { 0.5 },
+            ..Default::default()
+        }
+    }
+
+    /// Everything that can be detected from the contents.
+    pub fn build(path: &Path, file_type: &FileType) -> std::io::Result<ContentInfo> {
+        let mut info = ContentInfo::from_name(path);
+        info.detect_text(path, file_type)?;
+        info.detect_media(path, file_type);
+        Ok(info)
+    }
+
+    /// Reads the EXIF data of images.
+    pub fn detect_media(&mut self, path: &Path, file_type: &FileType) {
+        self.media_detected = true;
+        if *file_type == FileType::Image {
+            self.media_info = read_media_info(path);
+            self.has_metadata = self.media_info.is_some();
+        }
+    }
+
+    /// Detects the encoding and language of text, code and documents from
+    /// the start of the file.
+    pub fn detect_text(&mut self, path: &Path, file_type: &FileType) -> std::io::Result<()> {
+        self.text_detected = true;
+        if !matches!(
+            file_type,
+            FileType::Text | FileType::Code | FileType::Document
+        ) {
+            return Ok(());
+        }
+        let sample = read_sample(path)?;
+        let complete = (sample.len() as u64) < SAMPLE_SIZE;
+        if let Some(encoding) = detect_encoding(&sample, complete) {
+            self.text_encoding = Some(encoding_name(encoding, &sample));
+            let (text, _) = encoding.decode_with_bom_removal(&sample);
+            self.language = detect_language(&text);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,109 @@
+use regex::bytes::Regex;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+// Default cap on how much of a file a content filter will read (10 MB)
+pub const DEFAULT_MAX_BYTES: u64 = 10 * 1024 * 1024;
+
+// Size of the leading block checked for NUL bytes to detect binary files
+const BINARY_SNIFF_LEN: usize = 8 * 1024;
+
+/// Returns true if any line of the file contains `needle`.
+pub fn file_contains(path: &Path, needle: &str, max_bytes: Option<u64>) -> io::Result<bool> {
+    let needle = needle.as_bytes();
+    if needle.is_empty() {
+        return Ok(true);
+    }
+    search_lines(path, max_bytes, |line| {
+        line.windows(needle.len()).any(|window| window == needle)
+    })
+}
+
+/// Returns true if any line of the file matches `pattern`.
+pub fn file_matches_regex(
+    path: &Path,
+    pattern: &Regex,
+    max_bytes: Option<u64>,
+) -> io::Result<bool> {
+    search_lines(path, max_bytes, |line| pattern.is_match(line))
+}
+
+// Streams the file line by line, stopping at the first matching line or once
+// `max_bytes` have been read. Binary files never match.
+fn search_lines<F>(path: &Path, max_bytes: Option<u64>, mut is_match: F) -> io::Result<bool>
+where
+    F: FnMut(&[u8]) -> bool,
+{
+    let limit = max_bytes.unwrap_or(DEFAULT_MAX_BYTES);
+    let mut reader = BufReader::new(File::open(path)?.take(limit));
+
+    let head = reader.fill_buf()?;
+    if head[..head.len().min(BINARY_SNIFF_LEN)].contains(&0) {
+        tracing::debug!("Skipping content search of binary file {}", path.display());
+        return Ok(false);
+    }
+
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        if reader.read_until(b'\n', &mut line)? == 0 {
+            return Ok(false);
+        }
+        // match like grep does, without the line terminator
+        let text = line.strip_suffix(b"\n").unwrap_or(&line);
+        let text = text.strip_suffix(b"\r").unwrap_or(text);
+        if is_match(text) {
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::NamedTempFile;
+
+    #[test]
+    fn test_file_contains() {
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), "first line\nINVOICE #1234\nlast line\n").unwrap();
+
+        assert!(file_contains(file.path(), "INVOICE", None).unwrap());
+        assert!(!file_contains(file.path(), "invoice", None).unwrap());
+    }
+
+    #[test]
+    fn test_file_matches_regex() {
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), "first line\nINVOICE #1234\nlast line\n").unwrap();
+
+        let pattern = Regex::new(r"(?i)invoice #\d+").unwrap();
+        assert!(file_matches_regex(file.path(), &pattern, None).unwrap());
+
+        let pattern = Regex::new(r"^last line$").unwrap();
+        assert!(file_matches_regex(file.path(), &pattern, None).unwrap());
+        let pattern = Regex::new(r"^line$").unwrap();
+        assert!(!file_matches_regex(file.path(), &pattern, None).unwrap());
+    }
+
+    #[test]
+    fn test_max_bytes_limits_search() {
+        let file = NamedTempFile::new().unwrap();
+        let mut contents = "x".repeat(100);
+        contents.push_str("needle");
+        fs::write(file.path(), contents).unwrap();
+
+        assert!(!file_contains(file.path(), "needle", Some(50)).unwrap());
+        assert!(file_contains(file.path(), "needle", Some(200)).unwrap());
+    }
+
+    #[test]
+    fn test_binary_files_are_skipped() {
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), b"\x7FELF\0\0needle").unwrap();
+
+        assert!(!file_contains(file.path(), "needle", None).unwrap());
+    }
+}
@@ -1,99 +1,875 @@
-use std::fs::{self, Metadata};
+use std::collections::HashSet;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
-use crate::config::Filter;
-use crate::file_metadata::{FileContext, FileMetadata, FileMetadataError};
+use crate::cache::{self, Cache, Stamp};
+use crate::config::{Config, Filter, Location, Mode, Rule, Timestamp};
+use crate::content_info::ContentInfo;
+use crate::content_search::{file_contains, file_matches_regex};
+use crate::exclude::Excludes;
+use crate::file_metadata::{DirStats, FileContext, FileMetadata, FileMetadataError};
+use crate::handlers::action::Outcome;
+use crate::mounts::MountTable;
+use crate::state;
+use crate::text_detector::{encoding_matches, language_matches};
+
+/// A file or directory that a rule matched, and where its actions left it.
+#[derive(Debug)]
+pub struct Handled<'a> {
+    pub rule: &'a Rule,
+    pub context: FileContext,
+    pub outcome: Outcome,
+}
+
+/// Rules whose locations lie in one directory tree, so that a single walk
+/// from `root` covers all of them. Locations below the root are picked up
+/// when the walk gets there.
+#[derive(Debug)]
+pub struct Tree<'a> {
+    pub root: &'a Path,
+    // in config order
+    pub scopes: Vec<(&'a Location, &'a Rule)>,
+}
+
+impl<'a> Tree<'a> {
+    /// The distinct locations in the tree, sorted.
+    pub fn locations(&self) -> Vec<&'a Path> {
+        let mut locations: Vec<&Path> = self
+            .scopes
+            .iter()
+            .map(|(location, _)| location.path.as_path())
+            .collect();
+        locations.sort();
+        locations.dedup();
+        locations
+    }
+}
+
+/// Groups the locations of `rules` into trees, in order of first appearance.
+/// Paths are compared as written, without resolving symlinks.
+pub fn group_by_tree<'a, I>(rules: I) -> Vec<Tree<'a>>
+where
+    I: IntoIterator<Item = &'a Rule>,
+{
+    let mut scopes: Vec<(usize, &Location, &Rule)> = Vec::new();
+    for rule in rules {
+        for location in &rule.locations {
+            scopes.push((scopes.len(), location, rule));
+        }
+    }
+
+    // outer locations first, so every tree starts at its top
+    let mut by_depth = scopes.clone();
+    by_depth.sort_by_key(|(_, location, _)| location.path.components().count());
+    let mut trees: Vec<(usize, Tree)> = Vec::new();
+    for (index, location, rule) in by_depth {
+        match trees
+            .iter_mut()
+            .find(|(_, tree)| location.path.starts_with(tree.root))
+        {
+            Some((first, tree)) => {
+                *first = (*first).min(index);
+                tree.scopes.push((location, rule));
+            }
+            None => trees.push((
+                index,
+                Tree {
+                    root: &location.path,
+                    scopes: vec![(location, rule)],
+                },
+            )),
+        }
+    }
+
+    trees.sort_by_key(|(first, _)| *first);
+    trees
+        .into_iter()
+        .map(|(_, mut tree)| {
+            tree.scopes.sort_by_key(|(location, rule)| {
+                scopes
+                    .iter()
+                    .position(|(_, l, r)| std::ptr::eq(*l, *location) && std::ptr::eq(*r, *rule))
+            });
+            tree
+        })
+        .collect()
+}
 
 // return FileMetadata/FileContext
 pub fn search_dir(
     dir: &Path,
-    config: &crate::config::Config,
-    rule: &crate::config::Rule,
+    config: &Config,
+    rule: &Rule,
     quiet: bool,
 ) -> Result<Vec<FileContext>, FileMetadataError> {
+    search_location(&Location::from(dir.to_path_buf()), config, rule, quiet)
+}
+
+/// Like `search_dir`, honouring the options set on the location.
+pub fn search_location(
+    location: &Location,
+    config: &Config,
+    rule: &Rule,
+    quiet: bool,
+) -> Result<Vec<FileContext>, FileMetadataError> {
+    let handled = search_rules(&single(location, rule), config, quiet)?;
+    Ok(handled.into_iter().map(|h| h.context).collect())
+}
+
+fn single<'a>(location: &'a Location, rule: &'a Rule) -> Tree<'a> {
+    Tree {
+        root: &location.path,
+        scopes: vec![(location, rule)],
+    }
+}
+
+/// Walks `tree` once for all of its rules. Each entry is checked against the
+/// rules in order; once a rule handled it, the config's `mode` and the rule's
+/// `stop` decide whether the next rules still get to see it.
+pub fn search_rules<'a>(
+    tree: &Tree<'a>,
+    config: &'a Config,
+    quiet: bool,
+) -> Result<Vec<Handled<'a>>, FileMetadataError> {
     // bail early
-    if !dir.is_dir() {
+    if !tree.root.is_dir() {
         return Ok(Vec::new());
     }
-    tracing::info!("Running rule {}", rule.name);
+    for (_, rule) in &tree.scopes {
+        tracing::info!("Running rule {}", rule.name);
+    }
 
-    // Read the directory entries
-    let entries = match fs::read_dir(dir) {
-        Ok(entries) => entries,
-        Err(e) => {
-            if !quiet {
-                tracing::error!("Warning: Could not read directory {}: {}", dir.display(), e);
-            }
-            return Ok(Vec::new());
+    let mut walk = Walk::new(tree, config, quiet)?;
+    for scope in &mut walk.scopes {
+        if scope.location.cache {
+            scope.cache = load_cache(scope.location, config, scope.rule);
+        }
+    }
+    walk.dir(tree.root, &[])?;
+    // a failed search leaves the last cache in place
+    for scope in &walk.scopes {
+        if let Some(cache) = &scope.cache
+            && let Err(e) = cache.save()
+        {
+            tracing::error!(
+                "Could not save the cache of {}: {}",
+                scope.location.path.display(),
+                e
+            );
+        }
+    }
+    Ok(walk.results)
+}
+
+/// Runs a rule on one path inside `location`, as if a search of the location
+/// had reached it. Paths outside the location, in subfolders the rule does not
+/// search, or below an excluded directory match nothing. The location's cache
+/// is not used, every path counts as new since the last run.
+pub fn search_path(
+    path: &Path,
+    location: &Location,
+    config: &Config,
+    rule: &Rule,
+    quiet: bool,
+) -> Result<Vec<FileContext>, FileMetadataError> {
+    let handled = search_path_rules(path, &single(location, rule), config, quiet)?;
+    Ok(handled.into_iter().map(|h| h.context).collect())
+}
+
+/// Like `search_path` for all rules of `tree`, in the same way as
+/// `search_rules`.
+pub fn search_path_rules<'a>(
+    path: &Path,
+    tree: &Tree<'a>,
+    config: &'a Config,
+    quiet: bool,
+) -> Result<Vec<Handled<'a>>, FileMetadataError> {
+    let Some(parent) = path.parent().filter(|p| p.starts_with(tree.root)) else {
+        return Ok(Vec::new());
+    };
+    let Ok(link_metadata) = fs::symlink_metadata(path) else {
+        return Ok(Vec::new());
+    };
+
+    let mut walk = Walk::new(tree, config, quiet)?;
+    let active = walk.down_to(parent);
+    walk.visit(path.to_path_buf(), link_metadata.is_symlink(), &active)?;
+    Ok(walk.results)
+}
+
+/// The directories a search of `tree` would enter from `dir` down, `dir`
+/// included: those some rule searches without an exclude, ignore file or
+/// filesystem boundary in the way. Symlinks are not followed.
+pub fn entered_dirs(
+    dir: &Path,
+    tree: &Tree,
+    config: &Config,
+) -> Result<Vec<PathBuf>, FileMetadataError> {
+    if !dir.starts_with(tree.root) || !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+    let mut walk = Walk::new(tree, config, true)?;
+    let active = walk.down_to(dir);
+    let mut dirs = Vec::new();
+    walk.entered_dirs(dir, &active, &mut dirs);
+    Ok(dirs)
+}
+
+/// Everything filters look at for one path, the way a search builds it.
+/// Directory totals are always worked out, the contents only looked at when a
+/// rule reads them, and no cache is read, so the path counts as new since the
+/// last run.
+pub fn describe(path: &Path, config: &Config) -> Result<FileContext, FileMetadataError> {
+    let metadata = FileMetadata::build(path, config, false)?;
+    let filters = || config.rules.iter().flat_map(|rule| &rule.filters);
+    let content_info = if metadata.is_file {
+        let mut info = ContentInfo::from_name(path);
+        if filters().any(Filter::reads_text) {
+            info.detect_text(path, &metadata.file_type)?;
+        }
+        if filters().any(Filter::reads_taken) {
+            info.detect_media(path, &metadata.file_type);
         }
+        Some(info)
+    } else {
+        None
     };
+    let dir_stats = if metadata.is_dir {
+        Some(DirStats::build(path)?)
+    } else {
+        None
+    };
+    Ok(FileContext {
+        parent_dir: get_parent_dir(path),
+        path: path.to_path_buf(),
+        metadata,
+        content_info,
+        base_dir: PathBuf::new(),
+        dir_stats,
+        new_since_last_run: true,
+    })
+}
+
+// State for a single walk over a tree, shared by the rules searching it
+struct Walk<'a> {
+    root: PathBuf,
+    config: &'a Config,
+    scopes: Vec<Scope<'a>>,
+    // (device, inode) of every directory entered
+    visited: HashSet<(u64, u64)>,
+    // only loaded when a rule skips filesystem types
+    mounts: Option<MountTable>,
+    quiet: bool,
+    results: Vec<Handled<'a>>,
+}
+
+// One rule searching one location of the tree
+struct Scope<'a> {
+    rule: &'a Rule,
+    location: &'a Location,
+    excludes: Excludes,
+    root_dev: u64,
+    // only for locations with `cache = true`
+    cache: Option<Cache>,
+}
+
+impl Scope<'_> {
+    fn targets(&self, is_dir: bool) -> bool {
+        if is_dir {
+            self.rule.targets.dirs()
+        } else {
+            self.rule.targets.files()
+        }
+    }
+}
+
+// An entry as every rule sees it, built once and shared
+struct Probe {
+    ctx: FileContext,
+    // scopes whose cache had the entry unchanged
+    unchanged: Vec<usize>,
+    // scopes whose cache takes the entry, and what it was stamped with
+    caching: Vec<usize>,
+    stamp: Option<(Stamp, Option<u64>)>,
+}
+
+impl<'a> Walk<'a> {
+    fn new(
+        tree: &Tree<'a>,
+        config: &'a Config,
+        quiet: bool,
+    ) -> Result<Walk<'a>, FileMetadataError> {
+        let root_dev = fs::metadata(tree.root)?.dev();
+        let scopes: Vec<Scope> = tree
+            .scopes
+            .iter()
+            .map(|(location, rule)| Scope {
+                rule,
+                location,
+                excludes: Excludes::new(&location.path, config, rule),
+                // locations below the root that are missing are never reached
+                root_dev: fs::metadata(&location.path)
+                    .map(|metadata| metadata.dev())
+                    .unwrap_or(root_dev),
+                cache: None,
+            })
+            .collect();
+        Ok(Walk {
+            root: tree.root.to_path_buf(),
+            config,
+            mounts: load_mounts(&scopes),
+            scopes,
+            visited: HashSet::new(),
+            quiet,
+            results: Vec::new(),
+        })
+    }
+
+    // Takes the way a walk would have taken down to `dir`, loading the ignore
+    // files of every directory. Returns the scopes that entered it.
+    fn down_to(&mut self, dir: &Path) -> Vec<usize> {
+        let mut active: Vec<usize> = Vec::new();
+        let depth = self.root.components().count();
+        for dir in dir.ancestors().collect::<Vec<_>>().into_iter().rev() {
+            if dir.components().count() < depth {
+                continue;
+            }
+            active.retain(|i| {
+                let scope = &self.scopes[*i];
+                scope.rule.subfolders
+                    && !scope.excludes.is_excluded(dir, true)
+                    && !self.is_other_filesystem(scope, dir)
+                    && (!dir.is_symlink() || scope.rule.follow_symlinks)
+            });
+            active.extend(self.starting_at(dir));
+            active.sort_unstable();
+            for i in &active {
+                self.scopes[*i].excludes.enter_dir(dir);
+            }
+        }
+        active
+    }
 
-    let mut results = Vec::new();
-    // Iterate over each entry in the directory
-    for entry_result in entries {
-        let entry = match entry_result {
-            Ok(entry) => entry,
+    // Collects `dir` if `active` scopes entered it, and the directories below
+    // it the way `visit` would descend. The scopes have entered `dir` already.
+    fn entered_dirs(&mut self, dir: &Path, active: &[usize], dirs: &mut Vec<PathBuf>) {
+        if !active.is_empty() {
+            dirs.push(dir.to_path_buf());
+        }
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            if !entry.file_type().is_ok_and(|t| t.is_dir()) {
+                continue;
+            }
+            let path = entry.path();
+            let mut entering: Vec<usize> = active
+                .iter()
+                .copied()
+                .filter(|i| {
+                    let scope = &self.scopes[*i];
+                    scope.rule.subfolders
+                        && !scope.excludes.is_excluded(&path, true)
+                        && !self.is_other_filesystem(scope, &path)
+                })
+                .collect();
+            entering.extend(self.starting_at(&path));
+            entering.sort_unstable();
+            let leads_to_location = self
+                .scopes
+                .iter()
+                .any(|scope| scope.location.path.starts_with(&path));
+            if entering.is_empty() && !leads_to_location {
+                continue;
+            }
+            for i in &entering {
+                self.scopes[*i].excludes.enter_dir(&path);
+            }
+            self.entered_dirs(&path, &entering, dirs);
+            for i in &entering {
+                self.scopes[*i].excludes.leave_dir();
+            }
+        }
+    }
+
+    // Scopes whose location is `dir`
+    fn starting_at(&self, dir: &Path) -> Vec<usize> {
+        (0..self.scopes.len())
+            .filter(|i| self.scopes[*i].location.path == dir)
+            .collect()
+    }
+
+    // `active` are the scopes that reached this directory from above
+    fn dir(&mut self, dir: &Path, active: &[usize]) -> Result<(), FileMetadataError> {
+        // a directory reached a second time, e.g. through a symlink to one of
+        // its ancestors, is not searched again
+        if let Ok(metadata) = fs::metadata(dir)
+            && !self.visited.insert((metadata.dev(), metadata.ino()))
+        {
+            tracing::warn!("Skipping {}, already searched", dir.display());
+            return Ok(());
+        }
+        // scopes are in config order, which is the order rules get entries in
+        let mut active = active.to_vec();
+        active.extend(self.starting_at(dir));
+        active.sort_unstable();
+
+        // Read the directory entries
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
             Err(e) => {
-                // Could not access entry so print warning and keep seearching
-                if !quiet {
-                    tracing::error!("Warning: error accessing entry in {}: {}", dir.display(), e);
+                if !self.quiet {
+                    tracing::error!("Warning: Could not read directory {}: {}", dir.display(), e);
                 }
-                continue;
+                return Ok(());
             }
         };
 
-        let path = entry.path();
+        for i in &active {
+            self.scopes[*i].excludes.enter_dir(dir);
+        }
+        let result = self.entries(dir, entries, &active);
+        for i in &active {
+            self.scopes[*i].excludes.leave_dir();
+        }
 
-        // If the entry is a directory, recursively search it
-        if path.is_dir() && rule.subfolders {
-            search_dir(&path, config, rule, quiet)?;
-        } else {
-            // We have a file, check if file matches criteria
-            let fmeta = FileMetadata::build(&path, quiet)?;
-            if matches_filters(&path, &rule.filters) {
-                if let Err(e) = crate::handlers::action::run(&rule.actions, &path) {
-                    tracing::error!("Error applying actions to {}: {}", path.display(), e);
+        // subdirectories were pruned first, so this works bottom-up; a
+        // directory that was empty to begin with is left alone, and so are
+        // the locations themselves
+        let remove_empty_dirs = active
+            .iter()
+            .any(|i| self.scopes[*i].location.remove_empty_dirs);
+        if remove_empty_dirs
+            && self.starting_at(dir).is_empty()
+            && dir != self.root
+            && result.as_ref().is_ok_and(|n| *n > 0)
+        {
+            self.prune(dir);
+        }
+        result.map(|_| ())
+    }
+
+    fn is_other_filesystem(&self, scope: &Scope, path: &Path) -> bool {
+        other_filesystem(scope.rule, scope.root_dev, self.mounts.as_ref(), path).is_some()
+    }
+
+    fn prune(&self, dir: &Path) {
+        // only the link would go, not the directory it points to
+        if dir.is_symlink() {
+            return;
+        }
+        let is_empty = fs::read_dir(dir)
+            .map(|mut entries| entries.next().is_none())
+            .unwrap_or(false);
+        if !is_empty {
+            return;
+        }
+        match fs::remove_dir(dir) {
+            Ok(()) => tracing::info!("Removed empty directory {}", dir.display()),
+            Err(e) => {
+                if !self.quiet {
+                    tracing::error!("Warning: Could not remove {}: {}", dir.display(), e);
+                }
+            }
+        }
+    }
+
+    // Returns how many entries the directory had
+    fn entries(
+        &mut self,
+        dir: &Path,
+        entries: fs::ReadDir,
+        active: &[usize],
+    ) -> Result<usize, FileMetadataError> {
+        let mut count = 0;
+        // Iterate over each entry in the directory
+        for entry_result in entries {
+            count += 1;
+            let entry = match entry_result {
+                Ok(entry) => entry,
+                Err(e) => {
+                    // Could not access entry so print warning and keep seearching
+                    if !self.quiet {
+                        tracing::error!(
+                            "Warning: error accessing entry in {}: {}",
+                            dir.display(),
+                            e
+                        );
+                    }
                     continue;
                 }
-                let metadata = fmeta.clone();
-                results.push(FileContext {
-                    path: path.clone(),
-                    metadata,
-                    content_info: None,
-                    parent_dir: get_parent_dir(&path),
-                    base_dir: std::env::current_dir().unwrap(),
-                })
+            };
+
+            self.visit(
+                entry.path(),
+                entry.file_type().is_ok_and(|t| t.is_symlink()),
+                active,
+            )?;
+        }
+        Ok(count)
+    }
+
+    fn visit(
+        &mut self,
+        path: PathBuf,
+        is_symlink: bool,
+        active: &[usize],
+    ) -> Result<(), FileMetadataError> {
+        let is_dir = path.is_dir();
+        // excluded directories are pruned here, so their contents are never read
+        let seeing: Vec<usize> = active
+            .iter()
+            .copied()
+            .filter(|i| {
+                let scope = &self.scopes[*i];
+                if scope.excludes.is_excluded(&path, is_dir) {
+                    tracing::debug!("Excluded {} from {}", path.display(), scope.rule.name);
+                    return false;
+                }
+                if is_dir && self.is_other_filesystem(scope, &path) {
+                    tracing::debug!("Not crossing into {}", path.display());
+                    return false;
+                }
+                true
+            })
+            .collect();
+
+        // Rules get the entry in order. If the entry is a directory, recurse
+        // into it for every rule that searches subfolders and did not match
+        // and handle the directory as a whole. A rule that claims it keeps
+        // only the later rules out; the earlier ones still search below it.
+        let mut probe = None;
+        let mut descend = Vec::new();
+        for i in seeing.iter().copied() {
+            let rule = self.scopes[i].rule;
+            if self.scopes[i].targets(is_dir) {
+                let probe_ref = match &mut probe {
+                    Some(probe) => probe,
+                    None => probe.insert(self.probe(&path, is_dir, &seeing)?),
+                };
+                if let Some(outcome) = self.entry(i, probe_ref)? {
+                    if outcome != Outcome::At(path.clone()) {
+                        return Ok(());
+                    }
+                    if rule.stop || self.config.mode == Mode::FirstMatch {
+                        break;
+                    }
+                    // the actions may have changed it in place, so the
+                    // next rules look at it again
+                    probe = None;
+                    continue;
+                }
+            }
+            if is_dir && rule.subfolders && (!is_symlink || rule.follow_symlinks) {
+                descend.push(i);
+            }
+        }
+        // locations further down are reached even when no rule searches
+        // the directories in between
+        let leads_to_location = is_dir
+            && self
+                .scopes
+                .iter()
+                .any(|scope| scope.location.path.starts_with(&path));
+        if !descend.is_empty() || leads_to_location {
+            self.dir(&path, &descend)?;
+        }
+        Ok(())
+    }
+
+    // Builds what the rules in `seeing` look at, reusing what their caches
+    // know about an unchanged entry
+    fn probe(
+        &mut self,
+        path: &Path,
+        is_dir: bool,
+        seeing: &[usize],
+    ) -> Result<Probe, FileMetadataError> {
+        let caching: Vec<usize> = seeing
+            .iter()
+            .copied()
+            .filter(|i| self.scopes[*i].cache.is_some() && self.scopes[*i].targets(is_dir))
+            .collect();
+        if caching.is_empty() {
+            let fmeta = FileMetadata::build(path, self.config, self.quiet)?;
+            let content_info = fmeta.is_file.then(|| ContentInfo::from_name(path));
+            return Ok(Probe {
+                ctx: self.context(path, fmeta, content_info),
+                unchanged: Vec::new(),
+                caching,
+                stamp: None,
+            });
+        }
+
+        // broken symlinks can only be described by the link itself
+        let metadata = fs::metadata(path).or_else(|_| fs::symlink_metadata(path))?;
+        let stamp = Stamp::of(&metadata);
+        let caches = || {
+            caching
+                .iter()
+                .filter_map(|i| self.scopes[*i].cache.as_ref())
+        };
+        let unchanged: Vec<usize> = caching
+            .iter()
+            .copied()
+            .filter(|i| {
+                self.scopes[*i]
+                    .cache
+                    .as_ref()
+                    .is_some_and(|cache| cache.get(path, &stamp).is_some())
+            })
+            .collect();
+        let mut cached = caches().find_map(|cache| cache.get(path, &stamp)).cloned();
+        let mut hash = cached.as_ref().and_then(|entry| entry.hash);
+        // only a file the caches saw at the same size can turn out unchanged
+        if cached.is_none()
+            && metadata.is_file()
+            && caches().any(|cache| cache.has_size(path, stamp.size))
+        {
+            hash = cache::partial_hash(path).ok();
+            cached = hash.and_then(|hash| {
+                caches()
+                    .find_map(|cache| cache.get_by_hash(path, stamp.size, hash))
+                    .cloned()
+            });
+        }
+
+        let file_type = cached.as_ref().map(|entry| entry.file_type.clone());
+        let fmeta = FileMetadata::build_with_type(path, self.config, self.quiet, file_type)?;
+        let content_info = match &cached {
+            Some(entry) if fmeta.is_file => Some(entry.content_info(path)),
+            _ => fmeta.is_file.then(|| ContentInfo::from_name(path)),
+        };
+        let probe = Probe {
+            ctx: self.context(path, fmeta, content_info),
+            unchanged,
+            caching,
+            stamp: Some((stamp, hash)),
+        };
+        self.remember(&probe);
+        Ok(probe)
+    }
+
+    // Stores what is known about the entry in the caches that take it
+    fn remember(&mut self, probe: &Probe) {
+        let Some((stamp, hash)) = probe.stamp else {
+            return;
+        };
+        let ctx = &probe.ctx;
+        let entry = cache::Entry::new(
+            stamp,
+            hash,
+            &ctx.metadata.file_type,
+            ctx.content_info.as_ref(),
+        );
+        for i in probe.caching.iter().copied() {
+            if let Some(cache) = &mut self.scopes[i].cache {
+                cache.insert(ctx.path.clone(), entry.clone());
+            }
+        }
+    }
+
+    fn context(
+        &self,
+        path: &Path,
+        metadata: FileMetadata,
+        content_info: Option<ContentInfo>,
+    ) -> FileContext {
+        FileContext {
+            parent_dir: get_parent_dir(path),
+            path: path.to_path_buf(),
+            metadata,
+            content_info,
+            base_dir: self.root.clone(),
+            dir_stats: None,
+            new_since_last_run: true,
+        }
+    }
+
+    // Checks an entry against the rule of scope `i` and runs its actions.
+    // Returns where they left it when it matched.
+    fn entry(&mut self, i: usize, probe: &mut Probe) -> Result<Option<Outcome>, FileMetadataError> {
+        let scope = &self.scopes[i];
+        let rule = scope.rule;
+        probe.ctx.base_dir = scope.location.path.clone();
+        probe.ctx.new_since_last_run = !probe.unchanged.contains(&i);
+        // directory totals are only worked out once a rule asks for them
+        if probe.ctx.metadata.is_dir
+            && probe.ctx.dir_stats.is_none()
+            && rule.filters.iter().any(Filter::reads_dir_stats)
+        {
+            probe.ctx.dir_stats = self.dir_stats(&probe.ctx.path);
+        }
+        if !self.matches(&rule.filters, probe) {
+            return Ok(None);
+        }
+        let ctx = &probe.ctx;
+        let encoding = ctx
+            .content_info
+            .as_ref()
+            .and_then(|info| info.text_encoding.as_deref());
+        match crate::handlers::action::run(&rule.actions, &ctx.path, encoding) {
+            Ok(outcome) => {
+                self.results.push(Handled {
+                    rule,
+                    context: ctx.clone(),
+                    outcome: outcome.clone(),
+                });
+                Ok(Some(outcome))
+            }
+            Err(e) => {
+                tracing::error!("Error applying actions to {}: {}", ctx.path.display(), e);
+                // the actions that did run may have moved or deleted it
+                Ok(Some(match fs::symlink_metadata(&ctx.path) {
+                    Ok(_) => Outcome::At(ctx.path.clone()),
+                    Err(_) => Outcome::Consumed,
+                }))
+            }
+        }
+    }
+
+    // Filters are OR'd, so only open the file for content filters when none of
+    // the cheap metadata filters already matched.
+    fn matches(&mut self, filters: &[Filter], probe: &mut Probe) -> bool {
+        let (content, metadata): (Vec<&Filter>, Vec<&Filter>) =
+            filters.iter().partition(|filter| filter.reads_content());
+        if metadata
+            .iter()
+            .any(|filter| matches_filter(&probe.ctx, filter))
+        {
+            return true;
+        }
+        content.iter().any(|filter| {
+            self.detect(probe, filter);
+            matches_filter(&probe.ctx, filter)
+        })
+    }
+
+    // Runs the detections `filter` looks at that have not run on the entry
+    // yet. Directories and broken symlinks have no content to look at.
+    fn detect(&mut self, probe: &mut Probe, filter: &Filter) {
+        let ctx = &mut probe.ctx;
+        let Some(info) = &mut ctx.content_info else {
+            return;
+        };
+        let text = !info.text_detected && filter.reads_text();
+        let media = !info.media_detected && filter.reads_taken();
+        if text
+            && let Err(e) = info.detect_text(&ctx.path, &ctx.metadata.file_type)
+            && !self.quiet
+        {
+            tracing::error!("Warning: Could not read {}: {}", ctx.path.display(), e);
+        }
+        if media {
+            info.detect_media(&ctx.path, &ctx.metadata.file_type);
+        }
+        if text || media {
+            self.remember(probe);
+        }
+    }
+
+    fn dir_stats(&self, path: &Path) -> Option<DirStats> {
+        match DirStats::build(path) {
+            Ok(stats) => Some(stats),
+            Err(e) => {
+                if !self.quiet {
+                    tracing::error!(
+                        "Warning: Could not read directory {}: {}",
+                        path.display(),
+                        e
+                    );
+                }
+                None
             }
         }
     }
-    Ok(results)
 }
 
-fn get_created_time(metadata: &Metadata) -> Option<SystemTime> {
-    if let Ok(created_at) = metadata.created() {
-        // Success case: The pattern matched, we have the creation time.
-        Some(created_at)
-    } else {
-        // Failure case: The pattern did not match, it must be an Err.
-        // We can print a message or log the error before returning None.
-        None
+// One cache per rule and location, so `new_since_last_run` means since this
+// rule last searched the location
+fn load_cache(location: &Location, config: &Config, rule: &Rule) -> Option<Cache> {
+    let dir = config
+        .cache_dir
+        .clone()
+        .unwrap_or_else(|| state::default_dir().join("cache"));
+    let name = format!(
+        "{}-{:016x}.cache",
+        state::path_key(&location.path),
+        state::fnv1a(rule.name.as_bytes())
+    );
+    match Cache::load(&dir.join(name), &config.file_types) {
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            tracing::error!(
+                "Could not load the cache of {}, detecting every file: {}",
+                location.path.display(),
+                e
+            );
+            None
+        }
     }
 }
 
-fn get_access_time(metadata: &Metadata) -> Option<SystemTime> {
-    if let Ok(atime) = metadata.accessed() {
-        // Success case: The pattern matched, we have the creation time.
-        Some(atime)
-    } else {
-        // Failure case: The pattern did not match, it must be an Err.
-        // We can print a message or log the error before returning None.
-        None
+/// Why `rule` would not enter `path`: a directory on another device than its
+/// location (`root_dev`), or a mount point of a filesystem type it skips.
+pub(crate) fn other_filesystem(
+    rule: &Rule,
+    root_dev: u64,
+    mounts: Option<&MountTable>,
+    path: &Path,
+) -> Option<String> {
+    if !rule.same_filesystem && rule.skip_fstypes.is_empty() {
+        return None;
+    }
+    let metadata = fs::metadata(path).ok()?;
+    if rule.same_filesystem && metadata.dev() != root_dev {
+        return Some("on another filesystem and same_filesystem = true".to_string());
+    }
+    // only a mount point can change the filesystem type
+    let parent_dev = path
+        .parent()
+        .and_then(|parent| fs::metadata(parent).ok())
+        .map(|parent| parent.dev());
+    if parent_dev == Some(metadata.dev()) {
+        return None;
+    }
+    let fs_type = mounts?.fs_type(path)?;
+    rule.skip_fstypes
+        .iter()
+        .any(|t| t == fs_type)
+        .then(|| format!("a {} mount and in skip_fstypes", fs_type))
+}
+
+fn load_mounts(scopes: &[Scope]) -> Option<MountTable> {
+    if scopes
+        .iter()
+        .all(|scope| scope.rule.skip_fstypes.is_empty())
+    {
+        return None;
     }
+    mount_table()
 }
 
-fn matches_filters(path: &Path, filters: &[Filter]) -> bool {
-    filters.iter().any(|filter| match filter {
+/// The mount table, or `None` with an error logged when it cannot be read.
+pub(crate) fn mount_table() -> Option<MountTable> {
+    match MountTable::load() {
+        Ok(mounts) => Some(mounts),
+        Err(e) => {
+            tracing::error!(
+                "Could not read the mount table, not skipping any mounts: {}",
+                e
+            );
+            None
+        }
+    }
+}
+
+pub(crate) fn matches_filter(ctx: &FileContext, filter: &Filter) -> bool {
+    let path = ctx.path.as_path();
+    match filter {
         Filter::Extension { extension } => path
             .extension()
             .and_then(|ext| ext.to_str())
@@ -104,29 +880,149 @@ fn matches_filters(path: &Path, filters: &[Filter]) -> bool {
             .and_then(|name| name.to_str())
             .map(|name_str| name_str.contains(name_contains))
             .unwrap_or(false),
+        Filter::FileType { file_type } => file_type.contains(&ctx.metadata.file_type),
+        Filter::SizeCategory { size_category } => {
+            size_category.contains(&ctx.metadata.size_category)
+        }
+        Filter::AgeCategory { age_category } => age_category.contains(&ctx.metadata.age_category),
+        Filter::Encoding { encoding } => ctx
+            .content_info
+            .as_ref()
+            .and_then(|info| info.text_encoding.as_deref())
+            .map(|detected| encoding_matches(detected, encoding))
+            .unwrap_or(false),
+        Filter::Language { language } => ctx
+            .content_info
+            .as_ref()
+            .and_then(|info| info.language.as_deref())
+            .map(|detected| language_matches(detected, language))
+            .unwrap_or(false),
         Filter::Size { size_gt, size_lt } => {
-            if let Ok(metadata) = std::fs::metadata(path) {
-                let file_size = metadata.len();
-                let gt_pass = size_gt.map(|min| file_size > min).unwrap_or(true);
-                let lt_pass = size_lt.map(|max| file_size < max).unwrap_or(true);
-                gt_pass && lt_pass
-            } else {
-                false
-            }
+            let file_size = ctx.metadata.size;
+            let gt_pass = size_gt.map(|min| file_size > min).unwrap_or(true);
+            let lt_pass = size_lt.map(|max| file_size < max).unwrap_or(true);
+            gt_pass && lt_pass
         }
-        Filter::Age { days_older_than } => {
-            if let Some(days) = days_older_than {
-                if let Ok(metadata) = std::fs::metadata(path) {
-                    if let Ok(modified) = metadata.modified() {
-                        if let Ok(duration_since_mod) = modified.elapsed() {
-                            return duration_since_mod.as_secs() > (*days as u64 * 86400);
-                        }
-                    }
-                }
-            }
+        Filter::Owner { owner } => owner
+            .iter()
+            .any(|account| account.id == Some(ctx.metadata.uid)),
+        Filter::Group { group } => group
+            .iter()
+            .any(|account| account.id == Some(ctx.metadata.gid)),
+        Filter::Uid { uid } => uid.contains(&ctx.metadata.uid),
+        Filter::Gid { gid } => gid.contains(&ctx.metadata.gid),
+        Filter::ModeHas { mode_has } => ctx.metadata.permissions.mode() & mode_has == *mode_has,
+        Filter::ModeLacks { mode_lacks } => ctx.metadata.permissions.mode() & mode_lacks == 0,
+        Filter::XattrExists { xattr_exists } => {
+            ctx.metadata.extended_attributes.contains_key(xattr_exists)
+        }
+        Filter::XattrMissing { xattr_missing } => {
+            !ctx.metadata.extended_attributes.contains_key(xattr_missing)
+        }
+        Filter::XattrEquals { xattr_equals } => ctx
+            .metadata
+            .extended_attributes
+            .get(&xattr_equals.name)
+            .map(|value| value == xattr_equals.value.as_bytes())
+            .unwrap_or(false),
+        Filter::IsSymlink { is_symlink } => ctx.metadata.is_symlink == *is_symlink,
+        // exists() follows the link, so it is false when the target is missing
+        Filter::BrokenSymlink { broken_symlink } => {
+            (ctx.metadata.is_symlink && !path.exists()) == *broken_symlink
+        }
+        Filter::NewSinceLastRun { new_since_last_run } => {
+            ctx.new_since_last_run == *new_since_last_run
+        }
+        Filter::IsEmpty { is_empty } => ctx
+            .dir_stats
+            .as_ref()
+            .map(|stats| (stats.entries == 0) == *is_empty)
+            .unwrap_or(false),
+        Filter::TotalSizeGt { total_size_gt } => ctx
+            .dir_stats
+            .as_ref()
+            .map(|stats| stats.total_size > *total_size_gt)
+            .unwrap_or(false),
+        Filter::FileCountGt { file_count_gt } => ctx
+            .dir_stats
+            .as_ref()
+            .map(|stats| stats.file_count > *file_count_gt)
+            .unwrap_or(false),
+        Filter::NewestFileOlderThan {
+            newest_file_older_than,
+        } => ctx
+            .dir_stats
+            .as_ref()
+            .map(|stats| {
+                // a directory without files counts from its own modification time
+                let newest = stats.newest_file.unwrap_or(ctx.metadata.modified);
+                newest.elapsed().unwrap_or_default() > *newest_file_older_than
+            })
+            .unwrap_or(false),
+        Filter::ContentContains {
+            content_contains,
+            max_bytes,
+        } => search_content(ctx, |p| file_contains(p, content_contains, *max_bytes)),
+        Filter::ContentRegex {
+            content_regex,
+            max_bytes,
+        } => search_content(ctx, |p| file_matches_regex(p, content_regex, *max_bytes)),
+        Filter::Age {
+            days_older_than,
+            older_than,
+            newer_than,
+            before,
+            after,
+            timestamp,
+        } => {
+            let Some(time) = timestamp_of(ctx, *timestamp) else {
+                return false;
+            };
+            // timestamps in the future count as age zero
+            let age = time.elapsed().unwrap_or_default();
+            let conditions = [
+                days_older_than.map(|days| age.as_secs() > (days as u64 * 86400)),
+                older_than.map(|min| age > min),
+                newer_than.map(|max| age < max),
+                before.map(|limit| time < limit),
+                after.map(|limit| time > limit),
+            ];
+            // all given bounds must hold; a table without any never matches
+            conditions.iter().any(Option::is_some) && conditions.iter().flatten().all(|ok| *ok)
+        }
+        Filter::All(conditions) => conditions
+            .iter()
+            .all(|condition| matches_filter(ctx, condition)),
+    }
+}
+
+pub(crate) fn timestamp_of(ctx: &FileContext, timestamp: Timestamp) -> Option<SystemTime> {
+    match timestamp {
+        Timestamp::Modified => Some(ctx.metadata.modified),
+        Timestamp::Created => ctx.metadata.created,
+        Timestamp::Accessed => ctx.metadata.accessed,
+        Timestamp::Taken => ctx
+            .content_info
+            .as_ref()
+            .and_then(|info| info.media_info.as_ref())
+            .and_then(|media| media.date_taken),
+    }
+}
+
+fn search_content<F>(ctx: &FileContext, search: F) -> bool
+where
+    F: FnOnce(&Path) -> std::io::Result<bool>,
+{
+    if !ctx.metadata.is_file {
+        return false;
+    }
+    match search(&ctx.path) {
+        Ok(found) => found,
+        Err(e) => {
+            tracing::error!("Could not search contents of {}: {}", ctx.path.display(), e);
             false
         }
-    })
+    }
 }
 
 fn get_parent_dir(p: &Path) -> PathBuf {
@@ -0,0 +1,287 @@
+use crate::config::{Config, Rule};
+use crate::crawl::{group_by_tree, search_rules};
+use crate::lock::{PidLock, RunLocks};
+use crate::state::{self, LastRuns};
+use chrono::{DateTime, Local};
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+// The monotonic clock behind thread::sleep stops while the machine is
+// suspended, so sleep in short steps and check the wall clock in between.
+const MAX_SLEEP: Duration = Duration::from_secs(30);
+
+/// Runs every rule with a `schedule` or `every` on time until the process is
+/// stopped. Runs missed while the daemon was down or the machine slept are
+/// caught up once.
+pub fn run(
+    config: &Config,
+    config_path: &Path,
+    state_dir: &Path,
+    locks: &RunLocks,
+    quiet: bool,
+) -> io::Result<()> {
+    let (scheduled, unscheduled): (Vec<&Rule>, Vec<&Rule>) = config
+        .rules
+        .iter()
+        .partition(|rule| rule.schedule.is_some() || rule.every.is_some());
+    if scheduled.is_empty() {
+        return Err(io::Error::other("no rule has a schedule or every"));
+    }
+    for rule in unscheduled {
+        tracing::warn!("Rule {} has no schedule and will not run", rule.name);
+    }
+
+    fs::create_dir_all(state_dir)?;
+    let key = state::path_key(config_path);
+    let _lock = PidLock::acquire(&state_dir.join(format!("{}.pid", key)), false)?;
+    let state_path = state_dir.join(format!("{}.state", key));
+    let mut last_runs = LastRuns::load(&state_path)?;
+    // cron rules that never ran wait for the first slot after this
+    let started = SystemTime::now();
+
+    loop {
+        let now = SystemTime::now();
+        let due = due_rules(&scheduled, &last_runs, started, now);
+        let ran = if due.is_empty() {
+            Vec::new()
+        } else {
+            match locks.lock_config() {
+                Ok(_lock) => run_rules(config, &due, locks, quiet),
+                Err(e) => {
+                    tracing::warn!("Skipping scheduled run: {}", e);
+                    Vec::new()
+                }
+            }
+        };
+        if !ran.is_empty() {
+            for rule in &ran {
+                last_runs.set(&rule.name, now);
+            }
+            last_runs.save(&state_path)?;
+        }
+
+        let now = SystemTime::now();
+        let wait = scheduled
+            .iter()
+            .filter_map(|rule| next_run(rule, last_runs.get(&rule.name), started))
+            .min()
+            .map(|next| next.duration_since(now).unwrap_or_default())
+            .unwrap_or(MAX_SLEEP);
+        // rules a busy lock kept from running stay due, and are tried again
+        // after a while rather than in a loop
+        let wait = if ran.len() < due.len() {
+            MAX_SLEEP
+        } else {
+            wait.min(MAX_SLEEP)
+        };
+        std::thread::sleep(wait);
+    }
+}
+
+// The rules whose next run is not in the future
+fn due_rules<'a>(
+    rules: &[&'a Rule],
+    last_runs: &LastRuns,
+    started: SystemTime,
+    now: SystemTime,
+) -> Vec<&'a Rule> {
+    rules
+        .iter()
+        .filter(|rule| {
+            next_run(rule, last_runs.get(&rule.name), started).is_some_and(|next| next <= now)
+        })
+        .copied()
+        .collect()
+}
+
+// When a rule should run next. Interval rules that never ran are due at once;
+// cron rules wait for their first slot after `started`, the time the daemon
+// started. A slot missed since the last run is due now, however many were
+// missed.
+fn next_run(rule: &Rule, last_run: Option<SystemTime>, started: SystemTime) -> Option<SystemTime> {
+    if let Some(every) = rule.every {
+        return Some(last_run.map_or(started, |last| last + every));
+    }
+    let schedule = rule.schedule.as_ref()?;
+    let after = DateTime::<Local>::from(last_run.unwrap_or(started));
+    match schedule.find_next_occurrence(&after, false) {
+        Ok(next) => Some(next.into()),
+        Err(e) => {
+            tracing::error!("Rule {} will not run again: {}", rule.name, e);
+            None
+        }
+    }
+}
+
+// Due rules that share a directory tree search it together. Returns the rules
+// that ran: a failed search counts, as running it again would fail the same
+// way, but a rule with any location that was locked does not.
+fn run_rules<'a>(
+    config: &Config,
+    rules: &[&'a Rule],
+    locks: &RunLocks,
+    quiet: bool,
+) -> Vec<&'a Rule> {
+    let mut skipped: Vec<&Rule> = Vec::new();
+    for tree in group_by_tree(rules.iter().copied()) {
+        let _locks = match locks.lock_locations(&tree.locations()) {
+            Ok(locks) => locks,
+            Err(e) => {
+                tracing::warn!("Skipping {}: {}", tree.root.display(), e);
+                skipped.extend(tree.scopes.iter().map(|(_, rule)| *rule));
+                continue;
+            }
+        };
+        match search_rules(&tree, config, quiet) {
+            Ok(handled) => {
+                for (_, rule) in &tree.scopes {
+                    let files = handled.iter().filter(|h| h.rule.name == rule.name).count();
+                    tracing::info!(rule = rule.name, files, "Rule ran");
+                }
+            }
+            Err(e) => tracing::error!("Could not search {}: {}", tree.root.display(), e),
+        }
+    }
+    rules
+        .iter()
+        .copied()
+        .filter(|rule| !skipped.iter().any(|skipped| skipped.name == rule.name))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn rules(toml_content: &str) -> Config {
+        toml::from_str(toml_content).unwrap()
+    }
+
+    #[test]
+    fn test_next_run_every() {
+        let config = rules(
+            r#"
+[[rules]]
+name = "frequent"
+locations = ["/tmp"]
+subfolders = false
+every = "15m"
+filters = [{ extension = "pdf" }]
+actions = [{ echo = "Found pdf" }]
+"#,
+        );
+        let rule = &config.rules[0];
+        let now = SystemTime::now();
+        let last = now - Duration::from_secs(60);
+
+        assert_eq!(next_run(rule, None, now), Some(now));
+        assert_eq!(
+            next_run(rule, Some(last), now),
+            Some(last + Duration::from_secs(15 * 60))
+        );
+    }
+
+    #[test]
+    fn test_next_run_schedule_catches_up() {
+        let config = rules(
+            r#"
+[[rules]]
+name = "nightly"
+locations = ["/tmp"]
+subfolders = false
+schedule = "0 3 * * *"
+filters = [{ extension = "log" }]
+actions = [{ echo = "Found log" }]
+"#,
+        );
+        let rule = &config.rules[0];
+        let at = |day, hour| -> SystemTime {
+            Local
+                .with_ymd_and_hms(2024, 5, day, hour, 0, 0)
+                .unwrap()
+                .into()
+        };
+
+        // never ran: wait for the next 03:00
+        assert_eq!(next_run(rule, None, at(1, 12)), Some(at(2, 3)));
+        // last ran two days ago: the missed run is due immediately
+        let next = next_run(rule, Some(at(1, 3)), at(3, 12)).unwrap();
+        assert_eq!(next, at(2, 3));
+        assert!(next <= at(3, 12));
+    }
+
+    #[test]
+    fn test_never_run_schedule_fires_at_its_slot() {
+        let config = rules(
+            r#"
+[[rules]]
+name = "nightly"
+locations = ["/tmp"]
+subfolders = false
+schedule = "0 3 * * *"
+filters = [{ extension = "log" }]
+actions = [{ echo = "Found log" }]
+"#,
+        );
+        let scheduled: Vec<&Rule> = config.rules.iter().collect();
+        let at = |hour, minute| -> SystemTime {
+            Local
+                .with_ymd_and_hms(2024, 5, 1, hour, minute, 0)
+                .unwrap()
+                .into()
+        };
+        let started = at(2, 0);
+        let mut last_runs = LastRuns::default();
+
+        // one tick before the slot, the next one after it
+        assert!(due_rules(&scheduled, &last_runs, started, at(2, 59)).is_empty());
+        let due = due_rules(&scheduled, &last_runs, started, at(3, 0));
+        assert_eq!(due.len(), 1);
+        last_runs.set("nightly", at(3, 0));
+        assert!(due_rules(&scheduled, &last_runs, started, at(3, 1)).is_empty());
+    }
+
+    #[test]
+    fn test_locked_rules_did_not_run() {
+        use crate::config::LockScope;
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let (busy, free) = (temp_dir.path().join("busy"), temp_dir.path().join("free"));
+        fs::create_dir(&busy).unwrap();
+        fs::create_dir(&free).unwrap();
+        let config = rules(&format!(
+            r#"
+[[rules]]
+name = "busy"
+locations = ["{}"]
+subfolders = false
+every = "15m"
+filters = [{{ extension = "log" }}]
+actions = [{{ echo = "Found log" }}]
+
+[[rules]]
+name = "free"
+locations = ["{}"]
+subfolders = false
+every = "15m"
+filters = [{{ extension = "log" }}]
+actions = [{{ echo = "Found log" }}]
+"#,
+            busy.display(),
+            free.display()
+        ));
+        let locks_dir = temp_dir.path().join("locks");
+        let config_path = temp_dir.path().join("config.toml");
+        let locks = RunLocks::new(&locks_dir, &config_path, LockScope::Location, false).unwrap();
+        let _held = locks.lock_location(&busy).unwrap();
+
+        let due: Vec<&Rule> = config.rules.iter().collect();
+        let ran = run_rules(&config, &due, &locks, true);
+        assert_eq!(ran.len(), 1);
+        assert_eq!(ran[0].name, "free");
+    }
+}
@@ -0,0 +1,109 @@
+use crate::config::{Config, Rule};
+use ignore::Match;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use std::path::Path;
+
+// Per-directory ignore file read in addition to .gitignore
+pub const IGNORE_FILE: &str = ".sparkleignore";
+
+/// Decides which paths a traversal skips: the global and per-rule `exclude`
+/// patterns, plus `.gitignore`/`.sparkleignore` files found on the way down.
+/// All of them use gitignore syntax.
+pub struct Excludes {
+    patterns: Gitignore,
+    respect_gitignore: bool,
+    // one entry per directory entered, None when it had no ignore files
+    ignore_files: Vec<Option<Gitignore>>,
+}
+
+impl Excludes {
+    /// Patterns from the config are anchored at the location `root`.
+    pub fn new(root: &Path, config: &Config, rule: &Rule) -> Excludes {
+        let patterns = build_patterns(root, config.exclude.iter().chain(&rule.exclude))
+            .unwrap_or_else(|e| {
+                // Config::new validates patterns, so this only happens for
+                // configs built by hand
+                tracing::error!("Ignoring invalid exclude patterns: {}", e);
+                Gitignore::empty()
+            });
+        Excludes {
+            patterns,
+            respect_gitignore: config.respect_gitignore,
+            ignore_files: Vec::new(),
+        }
+    }
+
+    /// Loads the ignore files of `dir`. Must be paired with `leave_dir`.
+    pub fn enter_dir(&mut self, dir: &Path) {
+        let names: &[&str] = if self.respect_gitignore {
+            &[".gitignore", IGNORE_FILE]
+        } else {
+            &[IGNORE_FILE]
+        };
+
+        let mut builder = GitignoreBuilder::new(dir);
+        let mut found = false;
+        for name in names {
+            let ignore_file = dir.join(name);
+            if ignore_file.is_file() {
+                if let Some(e) = builder.add(&ignore_file) {
+                    tracing::warn!("Problem reading {}: {}", ignore_file.display(), e);
+                }
+                found = true;
+            }
+        }
+        let matcher = if found { builder.build().ok() } else { None };
+        self.ignore_files.push(matcher);
+    }
+
+    pub fn leave_dir(&mut self) {
+        self.ignore_files.pop();
+    }
+
+    pub fn is_excluded(&self, path: &Path, is_dir: bool) -> bool {
+        if is_dir && self.respect_gitignore && path.file_name() == Some(".git".as_ref()) {
+            return true;
+        }
+        if self.patterns.matched(path, is_dir).is_ignore() {
+            return true;
+        }
+        // the closest ignore file wins, and may re-include with `!pattern`
+        for matcher in self.ignore_files.iter().rev().flatten() {
+            match matcher.matched(path, is_dir) {
+                Match::Ignore(_) => return true,
+                Match::Whitelist(_) => return false,
+                Match::None => {}
+            }
+        }
+        false
+    }
+}
+
+fn build_patterns<'a, I>(root: &Path, patterns: I) -> Result<Gitignore, ignore::Error>
+where
+    I: IntoIterator<Item = &'a String>,
+{
+    let mut builder = GitignoreBuilder::new(root);
+    for pattern in patterns {
+        builder.add_line(None, pattern)?;
+    }
+    builder.build()
+}
+
+/// Checks that every pattern is a valid gitignore-style glob.
+pub fn validate_patterns(patterns: &[String]) -> Result<(), String> {
+    build_patterns(Path::new("/"), patterns)
+        .map(|_| ())
+        .map_err(|e| format!("invalid exclude pattern: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_patterns() {
+        assert!(validate_patterns(&["node_modules".to_string(), "*.tmp".to_string()]).is_ok());
+        assert!(validate_patterns(&["[z-a]".to_string()]).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+use crate::accounts;
+use crate::config::{Config, Filter, Location, Rule, XattrValue};
+use crate::crawl::{describe, matches_filter, mount_table, other_filesystem, timestamp_of};
+use crate::exclude::Excludes;
+use crate::file_metadata::{DirStats, FileContext, FileMetadataError};
+use crate::mounts::MountTable;
+use chrono::{DateTime, Local};
+use std::fmt::Write;
+use std::fs;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+/// Describes `path` and, for every rule, whether a search would reach it, what
+/// each filter compared and which actions would run. Nothing is changed.
+pub fn explain(path: &Path, config: &Config) -> Result<String, FileMetadataError> {
+    let ctx = describe(&absolute(path)?, config)?;
+    let mounts = config
+        .rules
+        .iter()
+        .any(|rule| !rule.skip_fstypes.is_empty())
+        .then(mount_table)
+        .flatten();
+    let mut out = String::new();
+    describe_file(&mut out, &ctx);
+    for rule in &config.rules {
+        out.push('\n');
+        explain_rule(&mut out, &ctx, config, rule, mounts.as_ref());
+    }
+    Ok(out)
+}
+
+// The path with its directory resolved, the way a search of a location would
+// come to it. The last component is kept, so a symlink stays one.
+fn absolute(path: &Path) -> Result<PathBuf, FileMetadataError> {
+    let Some(name) = path.file_name() else {
+        return Ok(fs::canonicalize(path)?);
+    };
+    let parent = match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => Path::new("/"),
+    };
+    Ok(fs::canonicalize(parent)?.join(name))
+}
+
+fn describe_file(out: &mut String, ctx: &FileContext) {
+    let meta = &ctx.metadata;
+    let _ = writeln!(out, "{}", ctx.path.display());
+    let _ = writeln!(
+        out,
+        "  type {}, size {} bytes ({}), modified {} ({})",
+        meta.file_type.as_str(),
+        meta.size,
+        meta.size_category.as_str(),
+        format_time(meta.modified),
+        meta.age_category.as_str()
+    );
+    if let Some(info) = &ctx.content_info {
+        let _ = write!(out, "  mime {}", info.mime_type);
+        // only detected when a rule filters on them
+        if info.text_detected {
+            let _ = write!(
+                out,
+                ", encoding {}, language {}",
+                info.text_encoding.as_deref().unwrap_or("-"),
+                info.language.as_deref().unwrap_or("-")
+            );
+        }
+        out.push('\n');
+    }
+    if let Some(stats) = &ctx.dir_stats {
+        let _ = writeln!(
+            out,
+            "  {} entries, {} files below it, {} bytes in total",
+            stats.entries, stats.file_count, stats.total_size
+        );
+    }
+}
+
+fn explain_rule(
+    out: &mut String,
+    ctx: &FileContext,
+    config: &Config,
+    rule: &Rule,
+    mounts: Option<&MountTable>,
+) {
+    let reached: Vec<(&Location, Result<(), String>)> = rule
+        .locations
+        .iter()
+        .map(|location| (location, reach(ctx, location, config, rule, mounts)))
+        .collect();
+    let results: Vec<(&Filter, bool)> = rule
+        .filters
+        .iter()
+        .map(|filter| (filter, matches_filter(ctx, filter)))
+        .collect();
+    let searched = reached.iter().any(|(_, reach)| reach.is_ok());
+    let matched = results.iter().any(|(_, matched)| *matched);
+
+    let verdict = match (searched, matched) {
+        (true, true) => "matches",
+        (true, false) => "does not match",
+        (false, true) => "would match, but does not search this path",
+        (false, false) => "does not match, and does not search this path",
+    };
+    let _ = writeln!(out, "Rule '{}': {}", rule.name, verdict);
+    for (location, reach) in &reached {
+        match reach {
+            Ok(()) => {
+                let _ = writeln!(out, "  location {}: searched", location.path.display());
+            }
+            Err(reason) => {
+                let _ = writeln!(
+                    out,
+                    "  location {}: not searched, {}",
+                    location.path.display(),
+                    reason
+                );
+            }
+        }
+    }
+    // filters are OR'd, the conditions within one AND'd
+    for (filter, matched) in &results {
+        if let Filter::All(conditions) = filter {
+            let _ = writeln!(out, "  [{}] all of", mark(*matched));
+            for condition in conditions {
+                let _ = writeln!(
+                    out,
+                    "      [{}] {}: {}",
+                    mark(matches_filter(ctx, condition)),
+                    condition,
+                    compared(ctx, condition)
+                );
+            }
+            continue;
+        }
+        let _ = writeln!(
+            out,
+            "  [{}] {}: {}",
+            mark(*matched),
+            filter,
+            compared(ctx, filter)
+        );
+    }
+    let _ = writeln!(
+        out,
+        "  actions{}: {}",
+        if rule.stop { " (then stop)" } else { "" },
+        rule.actions
+            .iter()
+            .map(|action| action.to_string())
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+}
+
+fn mark(matched: bool) -> &'static str {
+    if matched { "x" } else { " " }
+}
+
+// Why a search of `location` by `rule` would not get to the path
+fn reach(
+    ctx: &FileContext,
+    location: &Location,
+    config: &Config,
+    rule: &Rule,
+    mounts: Option<&MountTable>,
+) -> Result<(), String> {
+    let Ok(root) = fs::canonicalize(&location.path) else {
+        return Err("the location does not exist".to_string());
+    };
+    let Some(relative) = ctx
+        .path
+        .strip_prefix(&root)
+        .ok()
+        .filter(|relative| relative.components().next().is_some())
+    else {
+        return Err("outside the location".to_string());
+    };
+    if relative.components().count() > 1 && !rule.subfolders {
+        return Err("in a subfolder and subfolders = false".to_string());
+    }
+    // the walk starts from the location as written
+    let path = location.path.join(relative);
+    let root_dev = fs::metadata(&location.path)
+        .map(|metadata| metadata.dev())
+        .unwrap_or_default();
+
+    let mut excludes = Excludes::new(&location.path, config, rule);
+    let mut dir = location.path.clone();
+    excludes.enter_dir(&dir);
+    for component in relative.parent().into_iter().flat_map(Path::components) {
+        dir.push(component);
+        if excludes.is_excluded(&dir, true) {
+            return Err(format!("{} is excluded", dir.display()));
+        }
+        if let Some(reason) = other_filesystem(rule, root_dev, mounts, &dir) {
+            return Err(format!("{} is {}", dir.display(), reason));
+        }
+        if dir.is_symlink() && !rule.follow_symlinks {
+            return Err(format!(
+                "{} is a symlink and follow_symlinks = false",
+                dir.display()
+            ));
+        }
+        excludes.enter_dir(&dir);
+    }
+    let is_dir = ctx.metadata.is_dir;
+    if excludes.is_excluded(&path, is_dir) {
+        return Err("excluded".to_string());
+    }
+    if is_dir && let Some(reason) = other_filesystem(rule, root_dev, mounts, &path) {
+        return Err(reason);
+    }
+    if is_dir && !rule.targets.dirs() {
+        return Err("the rule only handles files".to_string());
+    }
+    if !is_dir && !rule.targets.files() {
+        return Err("the rule only handles directories".to_string());
+    }
+    Ok(())
+}
+
+// The value a filter looked at
+fn compared(ctx: &FileContext, filter: &Filter) -> String {
+    let meta = &ctx.metadata;
+    let path = ctx.path.as_path();
+    let dir_stats = |describe: &dyn Fn(&DirStats) -> String| {
+        ctx.dir_stats
+            .as_ref()
+            .map(describe)
+            .unwrap_or_else(|| "not a directory".to_string())
+    };
+    match filter {
+        Filter::Extension { .. } => path
+            .extension()
+            .map(|ext| format!("extension is {:?}", ext.to_string_lossy()))
+            .unwrap_or_else(|| "no extension".to_string()),
+        Filter::NameContains { .. } => path
+            .file_name()
+            .map(|name| format!("name is {:?}", name.to_string_lossy()))
+            .unwrap_or_else(|| "no name".to_string()),
+        Filter::FileType { .. } => format!("file type is {}", meta.file_type.as_str()),
+        Filter::SizeCategory { .. } => format!("size category is {}", meta.size_category.as_str()),
+        Filter::AgeCategory { .. } => format!("age category is {}", meta.age_category.as_str()),
+        Filter::Encoding { .. } => ctx
+            .content_info
+            .as_ref()
+            .and_then(|info| info.text_encoding.as_deref())
+            .map(|encoding| format!("encoding is {}", encoding))
+            .unwrap_or_else(|| "no text encoding detected".to_string()),
+        Filter::Language { .. } => ctx
+            .content_info
+            .as_ref()
+            .and_then(|info| info.language.as_deref())
+            .map(|language| format!("language is {}", language))
+            .unwrap_or_else(|| "no language detected".to_string()),
+        Filter::Size { .. } => format!("size is {} bytes", meta.size),
+        Filter::Owner { .. } | Filter::Uid { .. } => format!(
+            "owner is {} (uid {})",
+            accounts::user_name(meta.uid).unwrap_or_else(|| "unknown".to_string()),
+            meta.uid
+        ),
+        Filter::Group { .. } | Filter::Gid { .. } => format!(
+            "group is {} (gid {})",
+            accounts::group_name(meta.gid).unwrap_or_else(|| "unknown".to_string()),
+            meta.gid
+        ),
+        Filter::ModeHas { .. } | Filter::ModeLacks { .. } => {
+            format!("mode is {:04o}", meta.permissions.mode() & 0o7777)
+        }
+        Filter::XattrExists { xattr_exists: name }
+        | Filter::XattrMissing {
+            xattr_missing: name,
+        }
+        | Filter::XattrEquals {
+            xattr_equals: XattrValue { name, .. },
+        } => match meta.extended_attributes.get(name) {
+            Some(value) => format!("{} is {:?}", name, String::from_utf8_lossy(value)),
+            None => format!("{} is not set", name),
+        },
+        Filter::IsSymlink { .. } | Filter::BrokenSymlink { .. } => {
+            if !meta.is_symlink {
+                "not a symlink".to_string()
+            } else if path.exists() {
+                "symlink to an existing target".to_string()
+            } else {
+                "symlink to a missing target".to_string()
+            }
+        }
+        Filter::NewSinceLastRun { .. } => {
+            "explain does not read caches, so the path counts as new".to_string()
+        }
+        Filter::IsEmpty { .. } => dir_stats(&|stats| format!("{} entries", stats.entries)),
+        Filter::TotalSizeGt { .. } => {
+            dir_stats(&|stats| format!("{} bytes in total", stats.total_size))
+        }
+        Filter::FileCountGt { .. } => dir_stats(&|stats| format!("{} files", stats.file_count)),
+        Filter::NewestFileOlderThan { .. } => dir_stats(&|stats| match stats.newest_file {
+            Some(newest) => format!("newest file modified {}", format_time(newest)),
+            None => format!("no files, modified {}", format_time(meta.modified)),
+        }),
+        Filter::ContentContains { .. } | Filter::ContentRegex { .. } => {
+            "searched the contents".to_string()
+        }
+        Filter::Age { timestamp, .. } => match timestamp_of(ctx, *timestamp) {
+            Some(time) => format!("{} time is {}", timestamp.as_str(), format_time(time)),
+            None => format!("no {} time", timestamp.as_str()),
+        },
+        Filter::All(conditions) => conditions
+            .iter()
+            .map(|condition| compared(ctx, condition))
+            .collect::<Vec<_>>()
+            .join(", "),
+    }
+}
+
+// e.g. `2024-03-01 14:02:11 (3days 2h ago)`
+fn format_time(time: SystemTime) -> String {
+    let age = time.elapsed().unwrap_or_default();
+    let age = Duration::from_secs(age.as_secs());
+    format!(
+        "{} ({} ago)",
+        DateTime::<Local>::from(time).format("%Y-%m-%d %H:%M:%S"),
+        humantime::format_duration(age)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_explain_rules() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("nested")).unwrap();
+        let report = root.join("nested").join("report.pdf");
+        fs::write(&report, "pdf").unwrap();
+
+        let config: Config = toml::from_str(&format!(
+            r#"
+[[rules]]
+name = "pdfs"
+locations = ["{0}"]
+subfolders = true
+filters = [{{ extension = "pdf" }}, {{ name_contains = "draft" }}]
+actions = [{{ echo = "pdf" }}]
+
+[[rules]]
+name = "top level"
+locations = ["{0}"]
+subfolders = false
+filters = [{{ name_contains = "report" }}]
+actions = ["delete"]
+"#,
+            root.display()
+        ))
+        .unwrap();
+
+        let out = explain(&report, &config).unwrap();
+        assert!(out.contains("Rule 'pdfs': matches"), "{}", out);
+        assert!(out.contains(r#"[x] extension = "pdf": extension is "pdf""#));
+        assert!(out.contains(r#"[ ] name_contains = "draft": name is "report.pdf""#));
+        assert!(out.contains("Rule 'top level': would match, but does not search this path"));
+        assert!(out.contains("not searched, in a subfolder and subfolders = false"));
+        assert!(out.contains(r#"actions: echo = "pdf""#));
+        assert!(out.contains(r#"actions: "delete""#));
+        // nothing ran
+        assert!(report.exists());
+    }
+
+    #[test]
+    fn test_explain_resolves_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path();
+        fs::create_dir(root.join("real")).unwrap();
+        std::os::unix::fs::symlink(root.join("real"), root.join("link")).unwrap();
+        fs::write(root.join("real").join("report.pdf"), "pdf").unwrap();
+
+        // the location is written through the link, the path with `..`
+        let config: Config = toml::from_str(&format!(
+            r#"
+[[rules]]
+name = "pdfs"
+locations = ["{}"]
+subfolders = false
+same_filesystem = true
+filters = [{{ extension = "pdf" }}]
+actions = [{{ echo = "pdf" }}]
+"#,
+            root.join("link").display()
+        ))
+        .unwrap();
+
+        let path = root.join("real").join("..").join("link").join("report.pdf");
+        let out = explain(&path, &config).unwrap();
+        assert!(out.contains("Rule 'pdfs': matches"), "{}", out);
+        assert!(out.contains(": searched"), "{}", out);
+    }
+}
@@ -1,10 +1,15 @@
+use crate::config::{AgeThresholds, FileTypes, SizeThresholds};
 use crate::file_metadata::{self, AgeCategory, FileType, SizeCategory};
 use file_format::{FileFormat, Kind};
 use mime_guess2::mime;
-use std::time::SystemTime;
 use std::{fs::Metadata, os::unix::fs::MetadataExt, path::Path};
 
-pub fn get_file_type(f: &Path) -> crate::file_metadata::FileType {
+pub fn get_file_type(f: &Path, file_types: &FileTypes) -> crate::file_metadata::FileType {
+    // user supplied mappings win over the built-in table
+    if let Some(file_type) = extension(f).and_then(|ext| file_types.get(&ext)) {
+        return file_type.clone();
+    }
+
     let extension_result = guess_mime(f);
     if extension_result != FileType::Unknown {
         return extension_result;
@@ -15,10 +20,14 @@ pub fn get_file_type(f: &Path) -> crate::file_metadata::FileType {
         let fmt = FileFormat::from_file(f);
         match fmt {
             Ok(ff) => match ff.kind() {
-                Kind::Document => FileType::Document,
+                Kind::Document | Kind::Ebook | Kind::Presentation | Kind::Spreadsheet => {
+                    FileType::Document
+                }
                 Kind::Image => FileType::Image,
+                Kind::Video => FileType::Video,
+                Kind::Audio => FileType::Audio,
                 Kind::Other => FileType::Unknown,
-                Kind::Archive => FileType::Archive,
+                Kind::Archive | Kind::Compressed | Kind::Package => FileType::Archive,
                 _ => FileType::Unknown,
             },
             Err(_) => FileType::Unknown,
@@ -26,10 +35,9 @@ pub fn get_file_type(f: &Path) -> crate::file_metadata::FileType {
     } else {
         let mime = mime.first_or_octet_stream();
         match mime {
-            m if m == mime::IMAGE_GIF => FileType::Image,
-            m if m == mime::IMAGE_BMP => FileType::Image,
-            m if m == mime::IMAGE_JPEG => FileType::Image,
-            m if m == mime::IMAGE_SVG => FileType::Image,
+            m if m.type_() == mime::IMAGE => FileType::Image,
+            m if m.type_() == mime::VIDEO => FileType::Video,
+            m if m.type_() == mime::AUDIO => FileType::Audio,
             m if m == mime::APPLICATION_PDF => FileType::Document,
             m if m == mime::APPLICATION_JAVASCRIPT => FileType::Code,
             m if m == mime::TEXT_PLAIN => FileType::Text,
@@ -38,48 +46,74 @@ pub fn get_file_type(f: &Path) -> crate::file_metadata::FileType {
     }
 }
 
-fn guess_mime(path: &Path) -> FileType {
-    let ext = path
-        .extension()
+fn extension(path: &Path) -> Option<String> {
+    path.extension()
         .and_then(|e| e.to_str())
-        .unwrap_or("")
-        .to_lowercase();
+        .map(|e| e.to_lowercase())
+}
+
+// Built-in extension table. Extensions can be added or remapped with the
+// [file_types] config section.
+fn guess_mime(path: &Path) -> FileType {
+    let ext = extension(path).unwrap_or_default();
 
     match ext.as_str() {
-        "java" | "rs" | "rb" | "ex" | "go" | "js" => FileType::Code,
-        "md" => FileType::Document,
-        "yml" | "yaml" | "toml" => FileType::Configuration,
-        "txt" => FileType::Text,
+        "rs" | "py" | "pyw" | "pyi" | "js" | "mjs" | "cjs" | "ts" | "tsx" | "jsx" | "java"
+        | "kt" | "kts" | "scala" | "groovy" | "go" | "rb" | "ex" | "exs" | "erl" | "hrl" | "c"
+        | "h" | "cpp" | "cc" | "cxx" | "hpp" | "hh" | "hxx" | "cs" | "fs" | "swift" | "m"
+        | "mm" | "php" | "pl" | "pm" | "lua" | "r" | "dart" | "hs" | "ml" | "mli" | "clj"
+        | "cljs" | "elm" | "zig" | "nim" | "jl" | "sh" | "bash" | "zsh" | "fish" | "ps1"
+        | "bat" | "cmd" | "sql" | "vue" | "svelte" | "html" | "htm" | "css" | "scss" | "sass"
+        | "less" | "ipynb" => FileType::Code,
+        "md" | "markdown" | "rst" | "adoc" | "org" | "tex" | "pdf" | "doc" | "docx" | "odt"
+        | "rtf" | "pages" | "xls" | "xlsx" | "ods" | "numbers" | "ppt" | "pptx" | "odp" | "key"
+        | "epub" | "mobi" | "azw3" | "djvu" => FileType::Document,
+        "yml" | "yaml" | "toml" | "ini" | "cfg" | "conf" | "json" | "json5" | "jsonc" | "xml"
+        | "properties" | "env" | "plist" | "desktop" | "service" => FileType::Configuration,
+        "txt" | "text" | "log" | "csv" | "tsv" | "nfo" => FileType::Text,
+        "jpg" | "jpeg" | "png" | "gif" | "bmp" | "svg" | "webp" | "tif" | "tiff" | "heic"
+        | "heif" | "avif" | "ico" | "psd" | "xcf" | "raw" | "cr2" | "cr3" | "nef" | "arw"
+        | "dng" | "orf" | "rw2" => FileType::Image,
+        "mp4" | "m4v" | "mkv" | "mov" | "avi" | "wmv" | "flv" | "webm" | "mpeg" | "mpg" | "3gp"
+        | "ogv" | "mts" | "m2ts" | "vob" => FileType::Video,
+        "mp3" | "wav" | "flac" | "aac" | "ogg" | "oga" | "opus" | "m4a" | "wma" | "aiff"
+        | "aif" | "alac" | "mid" | "midi" | "ape" => FileType::Audio,
+        "zip" | "tar" | "gz" | "tgz" | "bz2" | "tbz2" | "xz" | "txz" | "zst" | "7z" | "rar"
+        | "lz" | "lzma" | "lz4" | "cab" | "iso" | "dmg" | "deb" | "rpm" | "apk" | "jar" | "war" => {
+            FileType::Archive
+        }
         _ => FileType::Unknown,
     }
 }
 
-pub fn get_file_size_category(metadata: &Metadata) -> crate::file_metadata::SizeCategory {
+pub fn get_file_size_category(
+    metadata: &Metadata,
+    thresholds: &SizeThresholds,
+) -> crate::file_metadata::SizeCategory {
     let fsize = metadata.size();
     match fsize {
-        0..1024 => SizeCategory::Tiny,
-        1024..=1_048_576 => SizeCategory::Small,
-        1_048_577..=104_857_600 => SizeCategory::Medium,
-        104_857_601..=1_073_741_824 => SizeCategory::Medium,
-        1_073_741_825..=u64::MAX => SizeCategory::Medium,
+        s if s < thresholds.tiny => SizeCategory::Tiny,
+        s if s < thresholds.small => SizeCategory::Small,
+        s if s < thresholds.medium => SizeCategory::Medium,
+        s if s < thresholds.large => SizeCategory::Large,
+        _ => SizeCategory::Huge,
     }
 }
 
-fn system_time_to_days(time: SystemTime) -> u64 {
-    let now = SystemTime::now();
-    let age = now.duration_since(time).expect("Time went backwards");
-    age.as_secs() / 86_400 // 86,400 seconds in a day
-}
-
-pub fn get_age_category(metadata: &Metadata) -> crate::file_metadata::AgeCategory {
+pub fn get_age_category(
+    metadata: &Metadata,
+    thresholds: &AgeThresholds,
+) -> crate::file_metadata::AgeCategory {
     if let Ok(fdate) = metadata.modified() {
-        let days = system_time_to_days(fdate);
-        match days {
-            0 => AgeCategory::Recent,
-            1..=7 => AgeCategory::Week,
-            8..=30 => AgeCategory::Month,
-            31..=365 => AgeCategory::Year,
-            366..=u64::MAX => AgeCategory::Old,
+        // files modified in the future count as brand new
+        let age = fdate.elapsed().unwrap_or_default().as_secs();
+        let days = |d: u64| d.saturating_mul(86_400); // 86,400 seconds in a day
+        match age {
+            a if a < days(thresholds.recent) => AgeCategory::Recent,
+            a if a < days(thresholds.week) => AgeCategory::Week,
+            a if a < days(thresholds.month) => AgeCategory::Month,
+            a if a < days(thresholds.year) => AgeCategory::Year,
+            _ => AgeCategory::Old,
         }
     } else {
         file_metadata::AgeCategory::Old
@@ -92,51 +126,84 @@ mod tests {
 
     #[test]
     fn test_get_file_type_by_extension() {
-        assert_eq!(get_file_type(Path::new("test.java")), FileType::Code);
-        assert_eq!(get_file_type(Path::new("test.rs")), FileType::Code);
-        assert_eq!(get_file_type(Path::new("test.js")), FileType::Code);
-        assert_eq!(get_file_type(Path::new("test.go")), FileType::Code);
-        assert_eq!(get_file_type(Path::new("test.rb")), FileType::Code);
-        assert_eq!(get_file_type(Path::new("test.ex")), FileType::Code);
+        assert_eq!(
+            get_file_type(Path::new("test.java"), &FileTypes::default()),
+            FileType::Code
+        );
+        assert_eq!(
+            get_file_type(Path::new("test.rs"), &FileTypes::default()),
+            FileType::Code
+        );
+        assert_eq!(
+            get_file_type(Path::new("test.js"), &FileTypes::default()),
+            FileType::Code
+        );
+        assert_eq!(
+            get_file_type(Path::new("test.go"), &FileTypes::default()),
+            FileType::Code
+        );
+        assert_eq!(
+            get_file_type(Path::new("test.rb"), &FileTypes::default()),
+            FileType::Code
+        );
+        assert_eq!(
+            get_file_type(Path::new("test.ex"), &FileTypes::default()),
+            FileType::Code
+        );
     }
 
     #[test]
     fn test_get_file_type_documents() {
-        assert_eq!(get_file_type(Path::new("readme.md")), FileType::Document);
+        assert_eq!(
+            get_file_type(Path::new("readme.md"), &FileTypes::default()),
+            FileType::Document
+        );
     }
 
     #[test]
     fn test_get_file_type_configuration() {
         assert_eq!(
-            get_file_type(Path::new("config.yml")),
+            get_file_type(Path::new("config.yml"), &FileTypes::default()),
             FileType::Configuration
         );
         assert_eq!(
-            get_file_type(Path::new("config.yaml")),
+            get_file_type(Path::new("config.yaml"), &FileTypes::default()),
             FileType::Configuration
         );
         assert_eq!(
-            get_file_type(Path::new("config.toml")),
+            get_file_type(Path::new("config.toml"), &FileTypes::default()),
             FileType::Configuration
         );
     }
 
     #[test]
     fn test_get_file_type_text() {
-        assert_eq!(get_file_type(Path::new("notes.txt")), FileType::Text);
+        assert_eq!(
+            get_file_type(Path::new("notes.txt"), &FileTypes::default()),
+            FileType::Text
+        );
     }
 
     #[test]
     fn test_get_file_type_unknown() {
-        assert_eq!(get_file_type(Path::new("file.xyz")), FileType::Unknown);
-        assert_eq!(get_file_type(Path::new("no_extension")), FileType::Unknown);
+        assert_eq!(
+            get_file_type(Path::new("file.xyz"), &FileTypes::default()),
+            FileType::Unknown
+        );
+        assert_eq!(
+            get_file_type(Path::new("no_extension"), &FileTypes::default()),
+            FileType::Unknown
+        );
     }
 
     #[test]
     fn test_get_file_type_case_insensitive() {
-        assert_eq!(get_file_type(Path::new("TEST.JAVA")), FileType::Code);
         assert_eq!(
-            get_file_type(Path::new("Config.YML")),
+            get_file_type(Path::new("TEST.JAVA"), &FileTypes::default()),
+            FileType::Code
+        );
+        assert_eq!(
+            get_file_type(Path::new("Config.YML"), &FileTypes::default()),
             FileType::Configuration
         );
     }
@@ -149,8 +216,9 @@ mod tests {
         let tiny_file = NamedTempFile::new().unwrap();
         fs::write(&tiny_file, vec![0u8; 512]).unwrap();
         let tiny_metadata = fs::metadata(tiny_file.path()).unwrap();
+        let thresholds = SizeThresholds::default();
         assert!(matches!(
-            get_file_size_category(&tiny_metadata),
+            get_file_size_category(&tiny_metadata, &thresholds),
             SizeCategory::Tiny
         ));
 
@@ -158,7 +226,7 @@ mod tests {
         fs::write(&small_file, vec![0u8; 5000]).unwrap();
         let small_metadata = fs::metadata(small_file.path()).unwrap();
         assert!(matches!(
-            get_file_size_category(&small_metadata),
+            get_file_size_category(&small_metadata, &thresholds),
             SizeCategory::Small
         ));
 
@@ -166,8 +234,113 @@ mod tests {
         fs::write(&medium_file, vec![0u8; 5_000_000]).unwrap();
         let medium_metadata = fs::metadata(medium_file.path()).unwrap();
         assert!(matches!(
-            get_file_size_category(&medium_metadata),
+            get_file_size_category(&medium_metadata, &thresholds),
             SizeCategory::Medium
         ));
     }
+
+    #[test]
+    fn test_get_file_size_category_large_and_huge() {
+        use std::fs;
+        use tempfile::NamedTempFile;
+
+        // shrink the thresholds so the test does not need gigabyte files
+        let thresholds = SizeThresholds {
+            tiny: 10,
+            small: 100,
+            medium: 1000,
+            large: 10_000,
+        };
+
+        let large_file = NamedTempFile::new().unwrap();
+        fs::write(&large_file, vec![0u8; 5000]).unwrap();
+        let large_metadata = fs::metadata(large_file.path()).unwrap();
+        assert!(matches!(
+            get_file_size_category(&large_metadata, &thresholds),
+            SizeCategory::Large
+        ));
+
+        let huge_file = NamedTempFile::new().unwrap();
+        fs::write(&huge_file, vec![0u8; 10_000]).unwrap();
+        let huge_metadata = fs::metadata(huge_file.path()).unwrap();
+        assert!(matches!(
+            get_file_size_category(&huge_metadata, &thresholds),
+            SizeCategory::Huge
+        ));
+    }
+
+    #[test]
+    fn test_get_age_category() {
+        use std::fs::{self, File};
+        use std::time::{Duration, SystemTime};
+        use tempfile::NamedTempFile;
+
+        let thresholds = AgeThresholds::default();
+        let file = NamedTempFile::new().unwrap();
+        let age_of = |days: u64| {
+            let mtime = SystemTime::now() - Duration::from_secs(days * 86_400 + 60);
+            File::options()
+                .write(true)
+                .open(file.path())
+                .unwrap()
+                .set_modified(mtime)
+                .unwrap();
+            get_age_category(&fs::metadata(file.path()).unwrap(), &thresholds)
+        };
+
+        assert_eq!(age_of(0), AgeCategory::Recent);
+        assert_eq!(age_of(3), AgeCategory::Week);
+        assert_eq!(age_of(7), AgeCategory::Month);
+        assert_eq!(age_of(100), AgeCategory::Year);
+        assert_eq!(age_of(400), AgeCategory::Old);
+    }
+
+    #[test]
+    fn test_get_file_type_builtin_table() {
+        let file_types = FileTypes::default();
+        let cases = [
+            ("script.py", FileType::Code),
+            ("main.c", FileType::Code),
+            ("report.docx", FileType::Document),
+            ("settings.json", FileType::Configuration),
+            ("server.log", FileType::Text),
+            ("photo.png", FileType::Image),
+            ("clip.mp4", FileType::Video),
+            ("song.flac", FileType::Audio),
+            ("backup.tar", FileType::Archive),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(
+                get_file_type(Path::new(name), &file_types),
+                expected,
+                "{}",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn test_get_file_type_config_overrides() {
+        let file_types: FileTypes = toml::from_str(
+            r#"
+code = ["ts", ".TXT"]
+ebook = ["epub", "mobi"]
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            get_file_type(Path::new("notes.txt"), &file_types),
+            FileType::Code
+        );
+        assert_eq!(
+            get_file_type(Path::new("book.epub"), &file_types),
+            FileType::Custom("ebook".to_string())
+        );
+        // untouched extensions still use the built-in table
+        assert_eq!(
+            get_file_type(Path::new("photo.png"), &file_types),
+            FileType::Image
+        );
+    }
 }
@@ -1,9 +1,11 @@
+use crate::config::Config;
 use crate::content_info::ContentInfo;
 use crate::file_detector::{get_age_category, get_file_size_category, get_file_type};
+use serde::Deserialize;
 use std::collections::HashMap;
 use std::fmt;
 use std::fs::{self, Metadata};
-use std::os::unix::fs::PermissionsExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
@@ -42,13 +44,60 @@ impl std::error::Error for FileMetadataError {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct FileContext {
     pub path: PathBuf,
     pub metadata: FileMetadata,
     pub content_info: Option<ContentInfo>, // MIME type, etc.
     pub parent_dir: PathBuf,
-    pub base_dir: PathBuf, // The root we're organizing from
+    pub base_dir: PathBuf,           // The root we're organizing from
+    pub dir_stats: Option<DirStats>, // Only for directories, when a filter needs it
+    pub new_since_last_run: bool,    // Always true without a location cache
+}
+
+/// Totals over everything below a directory, for the directory filters.
+#[derive(Debug, Clone, Default)]
+pub struct DirStats {
+    pub entries: u64,    // direct children of any kind
+    pub file_count: u64, // files at any depth
+    pub total_size: u64, // bytes in those files
+    pub newest_file: Option<SystemTime>,
+}
+
+impl DirStats {
+    /// Walks `dir` without following symlinks. Subdirectories that cannot be
+    /// read are left out of the totals.
+    pub fn build(dir: &Path) -> std::io::Result<DirStats> {
+        let mut stats = DirStats::default();
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            stats.entries += 1;
+            stats.add(&entry.path());
+        }
+        Ok(stats)
+    }
+
+    fn add(&mut self, path: &Path) {
+        let Ok(metadata) = fs::symlink_metadata(path) else {
+            return;
+        };
+        if metadata.is_dir() {
+            match fs::read_dir(path) {
+                Ok(entries) => {
+                    for entry in entries.flatten() {
+                        self.add(&entry.path());
+                    }
+                }
+                Err(e) => tracing::debug!("Could not read directory {}: {}", path.display(), e),
+            }
+        } else if metadata.is_file() {
+            self.file_count += 1;
+            self.total_size += metadata.len();
+            if let Ok(modified) = metadata.modified() {
+                self.newest_file = self.newest_file.max(Some(modified));
+            }
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -59,6 +108,8 @@ pub struct FileMetadata {
     pub modified: SystemTime,
     pub accessed: Option<SystemTime>,
     pub permissions: std::fs::Permissions,
+    pub uid: u32,
+    pub gid: u32,
     pub is_file: bool,
     pub is_dir: bool,
     pub is_symlink: bool,
@@ -80,6 +131,8 @@ impl Clone for FileMetadata {
             modified: self.modified,
             accessed: self.accessed,
             permissions: std::fs::Permissions::from_mode(self.permissions.mode()),
+            uid: self.uid,
+            gid: self.gid,
             is_file: self.is_file,
             is_dir: self.is_dir,
             is_symlink: self.is_symlink,
@@ -91,7 +144,9 @@ impl Clone for FileMetadata {
     }
 }
 
-#[derive(Debug, Clone)]
+// Default bounds, configurable under [categories.size]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum SizeCategory {
     Tiny,   // < 1KB
     Small,  // 1KB - 1MB
@@ -100,16 +155,19 @@ pub enum SizeCategory {
     Huge,   // > 1GB
 }
 
-#[derive(Debug, Clone)]
+// Default bounds, configurable under [categories.age]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum AgeCategory {
     Recent, // < 1 day
     Week,   // 1-7 days
-    Month,  // 1-30 days
+    Month,  // 7-30 days
     Year,   // 30 days - 1 year
     Old,    // > 1 year
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(from = "String")]
 pub enum FileType {
     Document,
     Image,
@@ -120,10 +178,36 @@ pub enum FileType {
     Configuration,
     Text,
     Unknown,
+    // User defined category from the [file_types] config section
+    Custom(String),
 }
 
-impl FileType {
+impl SizeCategory {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SizeCategory::Tiny => "tiny",
+            SizeCategory::Small => "small",
+            SizeCategory::Medium => "medium",
+            SizeCategory::Large => "large",
+            SizeCategory::Huge => "huge",
+        }
+    }
+}
+
+impl AgeCategory {
     pub fn as_str(&self) -> &'static str {
+        match self {
+            AgeCategory::Recent => "recent",
+            AgeCategory::Week => "week",
+            AgeCategory::Month => "month",
+            AgeCategory::Year => "year",
+            AgeCategory::Old => "old",
+        }
+    }
+}
+
+impl FileType {
+    pub fn as_str(&self) -> &str {
         match self {
             FileType::Document => "document",
             FileType::Image => "image",
@@ -134,13 +218,58 @@ impl FileType {
             FileType::Text => "text",
             FileType::Configuration => "configuration",
             FileType::Unknown => "unknown",
+            FileType::Custom(name) => name,
+        }
+    }
+
+    pub fn is_custom(&self) -> bool {
+        matches!(self, FileType::Custom(_))
+    }
+}
+
+impl From<String> for FileType {
+    fn from(name: String) -> Self {
+        match name.to_lowercase().as_str() {
+            "document" => FileType::Document,
+            "image" => FileType::Image,
+            "video" => FileType::Video,
+            "audio" => FileType::Audio,
+            "archive" => FileType::Archive,
+            "code" => FileType::Code,
+            "configuration" => FileType::Configuration,
+            "text" => FileType::Text,
+            "unknown" => FileType::Unknown,
+            other => FileType::Custom(other.to_string()),
         }
     }
 }
 
 impl FileMetadata {
-    pub fn build(path: &Path, quiet: bool) -> Result<FileMetadata, FileMetadataError> {
-        let metadata = fs::metadata(path)?;
+    pub fn build(
+        path: &Path,
+        config: &Config,
+        quiet: bool,
+    ) -> Result<FileMetadata, FileMetadataError> {
+        FileMetadata::build_with_type(path, config, quiet, None)
+    }
+
+    /// Like `build`, but takes `file_type` as known, e.g. from a cache,
+    /// instead of detecting it.
+    pub fn build_with_type(
+        path: &Path,
+        config: &Config,
+        quiet: bool,
+        file_type: Option<FileType>,
+    ) -> Result<FileMetadata, FileMetadataError> {
+        // Symlinks describe the file they point to, except broken ones which
+        // can only describe themselves
+        let link_metadata = fs::symlink_metadata(path)?;
+        let is_symlink = link_metadata.is_symlink();
+        let metadata = match fs::metadata(path) {
+            Ok(metadata) => metadata,
+            Err(_) if is_symlink => link_metadata,
+            Err(e) => return Err(e.into()),
+        };
         match metadata.modified() {
             Ok(modified_time) => {
                 let fm = FileMetadata {
@@ -149,13 +278,15 @@ impl FileMetadata {
                     created: get_created_time(&metadata),
                     accessed: get_access_time(&metadata),
                     permissions: metadata.permissions(),
+                    uid: metadata.uid(),
+                    gid: metadata.gid(),
                     is_file: metadata.is_file(),
                     is_dir: metadata.is_dir(),
-                    is_symlink: metadata.is_symlink(),
-                    extended_attributes: HashMap::new(),
-                    size_category: get_file_size_category(&metadata),
-                    age_category: get_age_category(&metadata),
-                    file_type: get_file_type(path),
+                    is_symlink,
+                    extended_attributes: get_user_xattrs(path),
+                    size_category: get_file_size_category(&metadata, &config.categories.size),
+                    age_category: get_age_category(&metadata, &config.categories.age),
+                    file_type: file_type.unwrap_or_else(|| get_file_type(path, &config.file_types)),
                 };
                 Ok(fm)
             }
@@ -173,31 +304,33 @@ impl FileMetadata {
     }
 }
 
-fn get_created_time(metadata: &Metadata) -> Option<SystemTime> {
-    if let Ok(created_at) = metadata.created() {
-        // Success case: The pattern matched, we have the creation time.
-        Some(created_at)
-    } else {
-        // Failure case: The pattern did not match, it must be an Err.
-        // We can print a message or log the error before returning None.
-        None
-    }
+// Only the user namespace is read; security/system/trusted attributes are
+// either privileged or not meaningful for rules.
+fn get_user_xattrs(path: &Path) -> HashMap<String, Vec<u8>> {
+    let names = match xattr::list_deref(path) {
+        Ok(names) => names,
+        Err(e) => {
+            tracing::debug!("Could not list xattrs of {}: {}", path.display(), e);
+            return HashMap::new();
+        }
+    };
+
+    names
+        .filter_map(|name| name.into_string().ok())
+        .filter(|name| name.starts_with("user."))
+        .filter_map(|name| match xattr::get_deref(path, &name) {
+            Ok(Some(value)) => Some((name, value)),
+            _ => None,
+        })
+        .collect()
 }
 
-fn get_access_time(metadata: &Metadata) -> Option<SystemTime> {
-    if let Ok(atime) = metadata.accessed() {
-        // Success case: The pattern matched, we have the creation time.
-        Some(atime)
-    } else {
-        // Failure case: The pattern did not match, it must be an Err.
-        // We can print a message or log the error before returning None.
-        None
-    }
+fn get_created_time(metadata: &Metadata) -> Option<SystemTime> {
+    // Not every filesystem records a birth time, so treat it as optional.
+    metadata.created().ok()
 }
 
-fn get_parent_dir(p: &Path) -> PathBuf {
-    match p.parent() {
-        Some(parent) => parent.to_path_buf(),
-        None => PathBuf::new(),
-    }
+fn get_access_time(metadata: &Metadata) -> Option<SystemTime> {
+    // atime may be disabled (noatime) or unsupported on some platforms.
+    metadata.accessed().ok()
 }
@@ -0,0 +1,504 @@
+use crate::config::Config;
+use serde::de::{
+    self, DeserializeOwned, DeserializeSeed, Deserializer, EnumAccess, IgnoredAny, MapAccess,
+    SeqAccess, Visitor,
+};
+use serde_path_to_error::Segment;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// The languages a configuration can be written in. All of them describe the
+/// same structure, e.g. a TOML `[[rules]]` table is an object in a JSON
+/// `"rules"` array.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// Picks the format by extension: `.json`, `.yaml` or `.yml`, and TOML
+    /// for everything else.
+    pub fn from_path(path: &Path) -> Format {
+        Format::from_extension(path).unwrap_or(Format::Toml)
+    }
+
+    pub fn from_extension(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "toml" => Some(Format::Toml),
+            "json" => Some(Format::Json),
+            "yaml" | "yml" => Some(Format::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Parses a whole document into a table, keeping the order of its keys.
+    /// TOML has no null, so JSON `null` and YAML `~` are left out, as if the
+    /// key was not there.
+    pub fn read(self, contents: &str) -> Result<toml::Table, ParseError> {
+        let json = match self {
+            Format::Toml => {
+                return toml::from_str(contents).map_err(|e| ParseError::toml(contents, &e));
+            }
+            Format::Json => serde_json::from_str(contents).map_err(ParseError::json)?,
+            Format::Yaml => serde_yaml_ng::from_str(contents).map_err(ParseError::yaml)?,
+        };
+        match without_nulls(json) {
+            Ok(Some(toml::Value::Table(table))) => Ok(table),
+            Ok(_) => Err(ParseError::new(
+                "expected a table at the top level".to_string(),
+            )),
+            Err(message) => Err(ParseError::new(message)),
+        }
+    }
+
+    /// Deserializes straight from the text, so errors keep their position.
+    /// `ignored` gets the path of every key `T` does not know.
+    pub fn deserialize<T: DeserializeOwned>(
+        self,
+        contents: &str,
+        mut ignored: impl FnMut(serde_ignored::Path),
+    ) -> Result<T, ParseError> {
+        let mut track = serde_path_to_error::Track::new();
+        let result = match self {
+            Format::Toml => toml::de::Deserializer::parse(contents)
+                .and_then(|de| {
+                    T::deserialize(serde_path_to_error::Deserializer::new(
+                        serde_ignored::Deserializer::new(de, &mut ignored),
+                        &mut track,
+                    ))
+                })
+                .map_err(|e| ParseError::toml(contents, &e)),
+            Format::Json => T::deserialize(serde_path_to_error::Deserializer::new(
+                serde_ignored::Deserializer::new(
+                    &mut serde_json::Deserializer::from_str(contents),
+                    &mut ignored,
+                ),
+                &mut track,
+            ))
+            .map_err(ParseError::json),
+            // YAML would otherwise want `!move /tmp` tags for actions
+            Format::Yaml => serde_yaml_ng::with::singleton_map_recursive::deserialize(
+                serde_path_to_error::Deserializer::new(
+                    serde_ignored::Deserializer::new(
+                        serde_yaml_ng::Deserializer::from_str(contents),
+                        &mut ignored,
+                    ),
+                    &mut track,
+                ),
+            )
+            .map_err(ParseError::yaml),
+        };
+        result.map_err(|e| ParseError {
+            path: steps(&track.path()),
+            ..e
+        })
+    }
+
+    /// The line and column of the value at `path` in `contents`, to point an
+    /// error found in a rewritten copy of the document at where it was
+    /// written. A path that leaves the document points at the last value on
+    /// it that exists.
+    pub fn position_of(self, contents: &str, path: &[Step]) -> Option<(usize, usize)> {
+        let probe = Probe { path };
+        let e = match self {
+            Format::Toml => ParseError::toml(
+                contents,
+                &toml::de::Deserializer::parse(contents)
+                    .and_then(|de| probe.deserialize(de))
+                    .err()?,
+            ),
+            Format::Json => ParseError::json(
+                probe
+                    .deserialize(&mut serde_json::Deserializer::from_str(contents))
+                    .err()?,
+            ),
+            Format::Yaml => ParseError::yaml(
+                probe
+                    .deserialize(serde_yaml_ng::Deserializer::from_str(contents))
+                    .err()?,
+            ),
+        };
+        e.position
+    }
+
+    /// The opposite of `read`. JSON and YAML have no date type, so TOML dates
+    /// are written as strings, which read back as the same date.
+    pub fn write(self, table: &toml::Table) -> Result<String, String> {
+        match self {
+            Format::Toml => toml::to_string_pretty(table).map_err(|e| e.to_string()),
+            Format::Json => serde_json::to_string_pretty(&without_dates(table))
+                .map(|json| json + "\n")
+                .map_err(|e| e.to_string()),
+            Format::Yaml => {
+                serde_yaml_ng::to_string(&without_dates(table)).map_err(|e| e.to_string())
+            }
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "toml" => Ok(Format::Toml),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            _ => Err(format!(
+                "unknown format '{}', expected toml, json or yaml",
+                s
+            )),
+        }
+    }
+}
+
+// None for null
+fn without_nulls(value: serde_json::Value) -> Result<Option<toml::Value>, String> {
+    Ok(Some(match value {
+        serde_json::Value::Null => return Ok(None),
+        serde_json::Value::Bool(value) => toml::Value::Boolean(value),
+        serde_json::Value::Number(number) => match number.as_i64() {
+            Some(value) => toml::Value::Integer(value),
+            None => match number.as_f64() {
+                Some(value) => toml::Value::Float(value),
+                None => return Err(format!("{} is out of range", number)),
+            },
+        },
+        serde_json::Value::String(value) => toml::Value::String(value),
+        serde_json::Value::Array(values) => {
+            let mut array = Vec::new();
+            for value in values {
+                array.extend(without_nulls(value)?);
+            }
+            toml::Value::Array(array)
+        }
+        serde_json::Value::Object(entries) => {
+            let mut table = toml::Table::new();
+            for (key, value) in entries {
+                if let Some(value) = without_nulls(value)? {
+                    table.insert(key, value);
+                }
+            }
+            toml::Value::Table(table)
+        }
+    }))
+}
+
+/// One step into a document: a key of a table or an index into an array.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    Key(String),
+    Index(usize),
+}
+
+// Enum variants are keys of a table in every format
+pub(crate) fn steps(path: &serde_path_to_error::Path) -> Vec<Step> {
+    let mut steps = Vec::new();
+    for segment in path.iter() {
+        steps.push(match segment {
+            Segment::Seq { index } => Step::Index(*index),
+            Segment::Map { key } => Step::Key(key.clone()),
+            Segment::Enum { variant } => Step::Key(variant.clone()),
+            Segment::Unknown => break,
+        });
+    }
+    steps
+}
+
+// Walks a document down `path` and fails at the value there, so the error
+// carries the position the parser was at
+struct Probe<'a> {
+    path: &'a [Step],
+}
+
+const PROBED: &str = "probed";
+
+impl<'de> DeserializeSeed<'de> for Probe<'_> {
+    type Value = ();
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        deserializer.deserialize_any(self)
+    }
+}
+
+impl<'de> Visitor<'de> for Probe<'_> {
+    type Value = ();
+
+    fn expecting(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("any value")
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        let Some((Step::Key(key), path)) = self.path.split_first() else {
+            return Err(de::Error::custom(PROBED));
+        };
+        while let Some(next) = map.next_key::<String>()? {
+            if next == *key {
+                map.next_value_seed(Probe { path })?;
+                return Err(de::Error::custom(PROBED));
+            }
+            map.next_value::<IgnoredAny>()?;
+        }
+        Err(de::Error::custom(PROBED))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        let Some((Step::Index(index), path)) = self.path.split_first() else {
+            return Err(de::Error::custom(PROBED));
+        };
+        for _ in 0..*index {
+            if seq.next_element::<IgnoredAny>()?.is_none() {
+                return Err(de::Error::custom(PROBED));
+            }
+        }
+        seq.next_element_seed(Probe { path })?;
+        Err(de::Error::custom(PROBED))
+    }
+
+    fn visit_some<D: Deserializer<'de>>(self, deserializer: D) -> Result<(), D::Error> {
+        self.deserialize(deserializer)
+    }
+
+    fn visit_bool<E: de::Error>(self, _: bool) -> Result<(), E> {
+        Err(E::custom(PROBED))
+    }
+
+    fn visit_i64<E: de::Error>(self, _: i64) -> Result<(), E> {
+        Err(E::custom(PROBED))
+    }
+
+    fn visit_u64<E: de::Error>(self, _: u64) -> Result<(), E> {
+        Err(E::custom(PROBED))
+    }
+
+    fn visit_f64<E: de::Error>(self, _: f64) -> Result<(), E> {
+        Err(E::custom(PROBED))
+    }
+
+    fn visit_str<E: de::Error>(self, _: &str) -> Result<(), E> {
+        Err(E::custom(PROBED))
+    }
+
+    fn visit_bytes<E: de::Error>(self, _: &[u8]) -> Result<(), E> {
+        Err(E::custom(PROBED))
+    }
+
+    fn visit_unit<E: de::Error>(self) -> Result<(), E> {
+        Err(E::custom(PROBED))
+    }
+
+    fn visit_none<E: de::Error>(self) -> Result<(), E> {
+        Err(E::custom(PROBED))
+    }
+
+    fn visit_enum<A: EnumAccess<'de>>(self, _: A) -> Result<(), A::Error> {
+        Err(de::Error::custom(PROBED))
+    }
+}
+
+fn without_dates(table: &toml::Table) -> toml::Table {
+    table
+        .iter()
+        .map(|(key, value)| (key.clone(), value_without_dates(value)))
+        .collect()
+}
+
+fn value_without_dates(value: &toml::Value) -> toml::Value {
+    match value {
+        toml::Value::Datetime(datetime) => toml::Value::String(datetime.to_string()),
+        toml::Value::Array(values) => {
+            toml::Value::Array(values.iter().map(value_without_dates).collect())
+        }
+        toml::Value::Table(table) => toml::Value::Table(without_dates(table)),
+        value => value.clone(),
+    }
+}
+
+/// Why a configuration could not be read, with the 1-based line and column
+/// of the problem when the parser knows them.
+#[derive(Debug)]
+pub struct ParseError {
+    pub message: String,
+    pub position: Option<(usize, usize)>,
+    // the value the problem is in, e.g. `rules`, 0, `subfolders`, as far as
+    // it is known
+    pub path: Vec<Step>,
+    // the included file the problem is in, if it is not in the configuration
+    pub file: Option<PathBuf>,
+}
+
+impl ParseError {
+    pub fn new(message: String) -> ParseError {
+        ParseError {
+            message,
+            position: None,
+            path: Vec::new(),
+            file: None,
+        }
+    }
+
+    pub(crate) fn toml(contents: &str, e: &toml::de::Error) -> ParseError {
+        let position = e.span().map(|span| {
+            let before = contents.get(..span.start).unwrap_or(contents);
+            let line = before.matches('\n').count() + 1;
+            let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+            (line, column)
+        });
+        ParseError {
+            message: e.message().trim().to_string(),
+            position,
+            path: Vec::new(),
+            file: None,
+        }
+    }
+
+    pub(crate) fn json(e: serde_json::Error) -> ParseError {
+        let position = (e.line() != 0).then(|| (e.line(), e.column()));
+        ParseError::with_position(e.to_string(), position)
+    }
+
+    pub(crate) fn yaml(e: serde_yaml_ng::Error) -> ParseError {
+        let position = e
+            .location()
+            .map(|location| (location.line(), location.column()));
+        ParseError::with_position(e.to_string(), position)
+    }
+
+    // JSON and YAML errors end in e.g. " at line 3 column 5", which the
+    // position already says
+    fn with_position(mut message: String, position: Option<(usize, usize)>) -> ParseError {
+        if let Some((line, column)) = position
+            && let Some(len) = message
+                .strip_suffix(&format!(" at line {} column {}", line, column))
+                .map(str::len)
+        {
+            message.truncate(len);
+        }
+        ParseError {
+            message,
+            position,
+            path: Vec::new(),
+            file: None,
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(file) = &self.file {
+            write!(f, "{}: ", file.display())?;
+        }
+        match self.position {
+            Some((line, column)) => write!(f, "line {}, column {}: {}", line, column, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Translates a configuration into another format. Only configurations that
+/// load are converted, and comments are not carried over. Included files are
+/// read from `dir` to check them, but stay as they are.
+pub fn convert(contents: &str, dir: &Path, from: Format, to: Format) -> Result<String, ParseError> {
+    Config::parse(contents, from, dir, |_| {})?;
+    let table = from.read(contents)?;
+    to.write(&table).map_err(ParseError::new)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TOML: &str = r#"
+exclude = ["*.part"]
+
+[[rules]]
+name = "old logs"
+locations = [{ path = "/tmp", cache = true }]
+subfolders = true
+schedule = "0 3 * * *"
+filters = [
+    { extension = "log", before = 2024-01-31 },
+    { file_type = ["image", "video"], size_gt = 1000 },
+]
+actions = [{ move = "/tmp/logs" }, { rename = { pattern = "a", replacement = "b" } }, "delete"]
+"#;
+
+    #[test]
+    fn test_formats_read_the_same_config() {
+        let expected = format!(
+            "{:?}",
+            Config::parse(TOML, Format::Toml, Path::new(""), |_| {})
+                .unwrap()
+                .0
+        );
+        for format in [Format::Json, Format::Yaml, Format::Toml] {
+            let converted = convert(TOML, Path::new(""), Format::Toml, format).unwrap();
+            let (config, _) = Config::parse(&converted, format, Path::new(""), |_| {}).unwrap();
+            assert_eq!(format!("{:?}", config), expected, "{}", converted);
+            // and back
+            let toml = convert(&converted, Path::new(""), format, Format::Toml).unwrap();
+            let (config, _) = Config::parse(&toml, Format::Toml, Path::new(""), |_| {}).unwrap();
+            assert_eq!(format!("{:?}", config), expected, "{}", toml);
+        }
+
+        let json = convert(TOML, Path::new(""), Format::Toml, Format::Json).unwrap();
+        assert!(json.contains(r#""before": "2024-01-31""#), "{}", json);
+        // keys stay in the order they were written
+        assert!(json.find(r#""name""#) < json.find(r#""actions""#));
+    }
+
+    #[test]
+    fn test_error_positions() {
+        let json = "{\n  \"rules\": [\n    { \"name\": 5 }\n  ]\n}";
+        let e = Config::parse(json, Format::Json, Path::new(""), |_| {})
+            .err()
+            .unwrap();
+        assert_eq!(e.position, Some((3, 15)));
+        assert_eq!(e.message, "invalid type: integer `5`, expected a string");
+
+        let yaml = "rules:\n  - name: pdfs\n    subfolders: maybe\n";
+        let e = Config::parse(yaml, Format::Yaml, Path::new(""), |_| {})
+            .err()
+            .unwrap();
+        assert_eq!(e.position.map(|(line, _)| line), Some(3), "{}", e);
+        assert!(e.to_string().starts_with("line 3, column "), "{}", e);
+
+        assert_eq!(Format::from_path(Path::new("sparkle.YML")), Format::Yaml);
+        assert_eq!(Format::from_path(Path::new("sparkle.conf")), Format::Toml);
+        assert!("xml".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn test_nulls_are_left_out() {
+        let json = r#"{
+  "cache_dir": null,
+  "rules": [
+    { "name": "pdfs", "locations": ["/tmp"], "subfolders": false, "every": null,
+      "filters": [{ "extension": "pdf" }], "actions": [{ "echo": "pdf" }] }
+  ]
+}"#;
+        let yaml = "cache_dir: ~\nrules:\n  - name: pdfs\n    locations: [/tmp]\n    subfolders: false\n    every: ~\n    filters: [{ extension: pdf }]\n    actions: [{ echo: pdf }]\n";
+        for (format, contents) in [(Format::Json, json), (Format::Yaml, yaml)] {
+            let (config, _) = Config::parse(contents, format, Path::new(""), |_| {}).unwrap();
+            assert!(config.cache_dir.is_none());
+            assert!(config.rules[0].every.is_none());
+
+            // also when the configuration is rewritten before it is read
+            let legacy = match format {
+                Format::Json => contents.replacen('{', r#"{ "filter_schema": "legacy","#, 1),
+                _ => format!("filter_schema: legacy\n{}", contents),
+            };
+            let (config, _) = Config::parse(&legacy, format, Path::new(""), |_| {}).unwrap();
+            assert!(config.cache_dir.is_none());
+
+            let toml = convert(contents, Path::new(""), format, Format::Toml).unwrap();
+            assert!(!toml.contains("cache_dir"), "{}", toml);
+        }
+    }
+}
@@ -1,101 +1,249 @@
 use std::fs;
 use std::io::Error;
-use std::path::Path;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
 use std::time::{SystemTime, UNIX_EPOCH};
 
-pub fn run(actions: &[crate::config::Action], file_path: &Path) -> Result<(), Error> {
+/// Where the file is after an action.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    At(PathBuf),
+    // deleted, nothing can run on it anymore
+    Consumed,
+}
+
+/// Runs `actions` in order, each on wherever the previous one left the file.
+/// `encoding` is the one detected for the file, if any filter looked.
+pub fn run(
+    actions: &[crate::config::Action],
+    file_path: &Path,
+    encoding: Option<&str>,
+) -> Result<Outcome, Error> {
+    let mut outcome = Outcome::At(file_path.to_path_buf());
     for action in actions {
-        match action {
-            crate::config::Action::Echo(message) => {
-                tracing::info!("Running action echo: {}", message);
-            }
-            crate::config::Action::Move(path_buf) => {
-                // check if destination directory exists
-                if !path_buf.exists() {
-                    let msg = format!(
-                        "Destination directory '{}' does not exist",
-                        path_buf.display()
-                    );
-                    return Err(std::io::Error::other(msg));
-                };
+        // rules are validated so that this can only happen for the last action
+        let Outcome::At(path) = &outcome else {
+            return Err(std::io::Error::other(format!(
+                "Cannot run {:?} on '{}', it was already deleted",
+                action,
+                file_path.display()
+            )));
+        };
+        outcome = apply(action, path, encoding)?;
+    }
+    Ok(outcome)
+}
 
-                tracing::info!("Moving file to {}", path_buf.to_string_lossy());
-                if let Err(e) = crate::utils::move_file(file_path, path_buf) {
-                    tracing::error!("There was an issue trying to run the move action {}", e);
-                    return Err(std::io::Error::other(format!(
-                        "Cannot move a directory named '{}'",
-                        e
-                    )));
-                };
-                tracing::info!(
-                    "Moved file {} to {}",
-                    file_path.to_string_lossy(),
-                    path_buf.as_path().to_string_lossy()
+fn apply(
+    action: &crate::config::Action,
+    file_path: &Path,
+    encoding: Option<&str>,
+) -> Result<Outcome, Error> {
+    let unchanged = Outcome::At(file_path.to_path_buf());
+    match action {
+        crate::config::Action::Echo(message) => {
+            tracing::info!("Running action echo: {}", message);
+            Ok(unchanged)
+        }
+        crate::config::Action::Move(path_buf) => {
+            // check if destination directory exists
+            if !path_buf.exists() {
+                let msg = format!(
+                    "Destination directory '{}' does not exist",
+                    path_buf.display()
                 );
-            }
-            crate::config::Action::Copy(path_buf) => {
-                tracing::debug!("Copying file to {}", path_buf.to_string_lossy());
-                if !path_buf.exists() {
-                    let msg = format!(
-                        "Destination directory '{}' does not exist",
-                        path_buf.display()
-                    );
-                    return Err(std::io::Error::other(msg));
-                };
+                return Err(std::io::Error::other(msg));
+            };
 
-                if let Err(e) = crate::utils::copy_file(file_path, path_buf) {
-                    tracing::error!("There was an issue trying to run the copy action {}", e);
+            tracing::info!("Moving file to {}", path_buf.to_string_lossy());
+            let moved = match crate::utils::move_file(file_path, path_buf) {
+                Ok(moved) => moved,
+                Err(e) => {
+                    tracing::error!("There was an issue trying to run the move action {}", e);
                     return Err(std::io::Error::other(format!(
-                        "Cannot copy a directory named '{}'",
+                        "Could not move '{}': {}",
+                        file_path.display(),
                         e
                     )));
                 }
-                tracing::info!(
-                    "Copied file {} to {}",
-                    file_path.to_string_lossy(),
-                    path_buf.as_path().to_string_lossy()
+            };
+            tracing::info!(
+                "Moved file {} to {}",
+                file_path.to_string_lossy(),
+                path_buf.as_path().to_string_lossy()
+            );
+            Ok(Outcome::At(moved))
+        }
+        crate::config::Action::Copy(path_buf) => {
+            tracing::debug!("Copying file to {}", path_buf.to_string_lossy());
+            if !path_buf.exists() {
+                let msg = format!(
+                    "Destination directory '{}' does not exist",
+                    path_buf.display()
                 );
+                return Err(std::io::Error::other(msg));
+            };
+
+            if let Err(e) = crate::utils::copy_file(file_path, path_buf) {
+                tracing::error!("There was an issue trying to run the copy action {}", e);
+                return Err(std::io::Error::other(format!(
+                    "Could not copy '{}': {}",
+                    file_path.display(),
+                    e
+                )));
             }
-            crate::config::Action::Delete => {
-                fs::remove_file(file_path)?;
-                tracing::info!("Deleted {}", file_path.display());
-            }
-            crate::config::Action::Rename {
-                pattern,
-                replacement,
-            } => {
-                let parent_dir = file_path.parent();
-                let replacement_file_path = parent_dir.unwrap().join(replacement);
-                tracing::info!(
-                    "Renaming file {} to {}",
-                    file_path.to_string_lossy(),
-                    replacement_file_path.to_string_lossy()
+            tracing::info!(
+                "Copied file {} to {}",
+                file_path.to_string_lossy(),
+                path_buf.as_path().to_string_lossy()
+            );
+            // later actions work on the original, not the copy
+            Ok(unchanged)
+        }
+        crate::config::Action::Delete => {
+            crate::utils::remove_path(file_path)?;
+            tracing::info!("Deleted {}", file_path.display());
+            Ok(Outcome::Consumed)
+        }
+        crate::config::Action::Archive {
+            destination,
+            format,
+        } => {
+            if !destination.is_dir() {
+                let msg = format!(
+                    "Destination directory '{}' does not exist",
+                    destination.display()
                 );
-                if replacement_file_path.exists() {
-                    // update replacement with timestam
-                    let epoch_seconds = SystemTime::now()
-                        .duration_since(UNIX_EPOCH)
-                        .expect("Time went backwards")
-                        .as_millis();
-                    let base = Path::new(replacement).file_stem().unwrap();
-                    let new_file_name = format!(
-                        "{}{}.{}",
-                        base.to_string_lossy(),
-                        epoch_seconds,
-                        file_path.extension().unwrap().to_string_lossy()
-                    );
-                    let seq = parent_dir.unwrap().join(new_file_name);
-                    tracing::debug!(
-                        "The replacement file {} exits, adding seq {}",
-                        replacement_file_path.to_string_lossy(),
-                        seq.to_string_lossy()
-                    );
-                    fs::rename(file_path, seq)?;
-                } else {
-                    fs::rename(file_path, replacement_file_path)?;
+                return Err(std::io::Error::other(msg));
+            };
+            let archive = crate::utils::archive(file_path, destination, *format)?;
+            tracing::info!("Archived {} to {}", file_path.display(), archive.display());
+            Ok(unchanged)
+        }
+        crate::config::Action::SetPermissions(mode) => {
+            // modes are written the way chmod takes them, e.g. 644
+            let octal = u32::from_str_radix(&mode.to_string(), 8).map_err(|_| {
+                std::io::Error::other(format!("Invalid permission mode '{}'", mode))
+            })?;
+            fs::set_permissions(file_path, fs::Permissions::from_mode(octal))?;
+            tracing::info!("Set permissions of {} to {}", file_path.display(), mode);
+            Ok(unchanged)
+        }
+        crate::config::Action::TranscodeToUtf8 => {
+            if crate::text_detector::transcode_to_utf8(file_path, encoding)? {
+                tracing::info!("Transcoded {} to UTF-8", file_path.display());
+            } else {
+                tracing::debug!("{} is already UTF-8", file_path.display());
+            }
+            Ok(unchanged)
+        }
+        crate::config::Action::SetXattr { name, value } => {
+            xattr::set(file_path, name, value.as_bytes())?;
+            tracing::info!("Set xattr {}={} on {}", name, value, file_path.display());
+            Ok(unchanged)
+        }
+        crate::config::Action::RemoveXattr(name) => {
+            // removing an attribute that is not there is not an error
+            if xattr::get(file_path, name)?.is_some() {
+                xattr::remove(file_path, name)?;
+                tracing::info!("Removed xattr {} from {}", name, file_path.display());
+            }
+            Ok(unchanged)
+        }
+        crate::config::Action::Rename {
+            pattern: _,
+            replacement,
+        } => {
+            // a relative path has an empty parent, and so does `/`
+            let parent_dir = file_path.parent().unwrap_or(Path::new(""));
+            let replacement_file_path = parent_dir.join(replacement);
+            tracing::info!(
+                "Renaming file {} to {}",
+                file_path.to_string_lossy(),
+                replacement_file_path.to_string_lossy()
+            );
+            if replacement_file_path.exists() {
+                // update replacement with timestam
+                let epoch_seconds = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .unwrap_or_default()
+                    .as_millis();
+                let base = Path::new(replacement).file_stem().unwrap_or_default();
+                let mut new_file_name = format!("{}{}", base.to_string_lossy(), epoch_seconds);
+                // directories usually have no extension to keep
+                if let Some(extension) = file_path.extension() {
+                    new_file_name.push('.');
+                    new_file_name.push_str(&extension.to_string_lossy());
                 }
+                let seq = parent_dir.join(new_file_name);
+                tracing::debug!(
+                    "The replacement file {} exits, adding seq {}",
+                    replacement_file_path.to_string_lossy(),
+                    seq.to_string_lossy()
+                );
+                fs::rename(file_path, &seq)?;
+                Ok(Outcome::At(seq))
+            } else {
+                fs::rename(file_path, &replacement_file_path)?;
+                Ok(Outcome::At(replacement_file_path))
             }
         }
     }
-    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Action;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_actions_follow_the_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let archive = temp_dir.path().join("archive");
+        let backup = temp_dir.path().join("backup");
+        fs::create_dir(&archive).unwrap();
+        fs::create_dir(&backup).unwrap();
+        let report = temp_dir.path().join("report.pdf");
+        fs::write(&report, "pdf").unwrap();
+
+        let actions = [
+            Action::Move(archive.clone()),
+            Action::Rename {
+                pattern: "*".to_string(),
+                replacement: "2024-report.pdf".to_string(),
+            },
+        ];
+        let outcome = run(&actions, &report, None).unwrap();
+        assert_eq!(outcome, Outcome::At(archive.join("2024-report.pdf")));
+        assert!(archive.join("2024-report.pdf").is_file());
+        assert!(!report.exists());
+
+        let moved = archive.join("2024-report.pdf");
+        let actions = [Action::Copy(backup.clone()), Action::Delete];
+        assert_eq!(run(&actions, &moved, None).unwrap(), Outcome::Consumed);
+        assert!(backup.join("2024-report.pdf").is_file());
+        assert!(!moved.exists());
+    }
+
+    #[test]
+    fn test_rename_directory_onto_existing_name() {
+        let temp_dir = TempDir::new().unwrap();
+        let photos = temp_dir.path().join("photos");
+        fs::create_dir(&photos).unwrap();
+        fs::create_dir(temp_dir.path().join("album")).unwrap();
+
+        let actions = [Action::Rename {
+            pattern: String::new(),
+            replacement: "album".to_string(),
+        }];
+        let Outcome::At(renamed) = run(&actions, &photos, None).unwrap() else {
+            panic!("Expected the directory to be renamed");
+        };
+        let name = renamed.file_name().unwrap().to_string_lossy().into_owned();
+        assert!(name.starts_with("album") && name != "album", "{}", name);
+        assert!(!name.contains('.'), "{}", name);
+        assert!(renamed.is_dir());
+        assert!(!photos.exists());
+    }
 }
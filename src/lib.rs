@@ -1,9 +1,22 @@
+pub mod accounts;
+pub mod cache;
+pub mod check;
 pub mod cli;
 pub mod config;
 pub mod content_info;
+pub mod content_search;
 pub mod crawl;
+pub mod daemon;
+pub mod exclude;
+pub mod explain;
 pub mod file_detector;
 pub mod file_metadata;
+pub mod format;
 pub mod handlers;
+pub mod lock;
+pub mod media_detector;
+pub mod mounts;
+pub mod state;
+pub mod text_detector;
 pub mod utils;
-
+pub mod watch;
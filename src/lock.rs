@@ -0,0 +1,205 @@
+use crate::config::LockScope;
+use crate::state;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+
+/// An exclusive `flock` on a file that holds the owner's PID. The kernel
+/// releases it when the lock is dropped or the process dies, however it dies.
+pub struct PidLock {
+    _file: File,
+}
+
+impl PidLock {
+    /// Takes the lock, blocking until it is free when `wait` is set. Otherwise
+    /// fails with `ErrorKind::WouldBlock` while another process holds it.
+    ///
+    /// A held flock means its owner is alive, whatever PID the file records:
+    /// a new owner may not have written its own yet, and a process in another
+    /// PID namespace looks dead from here. The PID only words the error. A
+    /// file left behind by a dead process holds no flock and is taken over.
+    pub fn acquire(path: &Path, wait: bool) -> io::Result<PidLock> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+        if let Err(e) = flock(&file, libc::LOCK_EX | libc::LOCK_NB) {
+            if e.kind() != io::ErrorKind::WouldBlock {
+                return Err(e);
+            }
+            if !wait {
+                let owner = match read_pid(&mut file) {
+                    Some(pid) if is_running(pid) => format!("process {}", pid),
+                    Some(pid) => format!(
+                        "another process (the recorded process {} is not running here, the owner may not have written its PID yet)",
+                        pid
+                    ),
+                    None => "another process".to_string(),
+                };
+                return Err(io::Error::new(
+                    io::ErrorKind::WouldBlock,
+                    format!("{} is locked by {}", path.display(), owner),
+                ));
+            }
+            tracing::info!("Waiting for {}", path.display());
+            flock(&file, libc::LOCK_EX)?;
+        }
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(PidLock { _file: file })
+    }
+}
+
+fn flock(file: &File, operation: libc::c_int) -> io::Result<()> {
+    // SAFETY: flock only operates on the descriptor, which `file` keeps open
+    if unsafe { libc::flock(file.as_raw_fd(), operation) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    contents.trim().parse().ok()
+}
+
+// Signal 0 only checks whether the process exists; EPERM means it does but
+// belongs to another user
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // SAFETY: kill with signal 0 never delivers a signal
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Keeps two Sparkle processes from running the same configuration, or the
+/// same location with `lock = "location"`, at the same time.
+pub struct RunLocks {
+    dir: PathBuf,
+    config_key: String,
+    scope: LockScope,
+    wait: bool,
+}
+
+impl RunLocks {
+    pub fn new(dir: &Path, config_path: &Path, scope: LockScope, wait: bool) -> io::Result<Self> {
+        fs::create_dir_all(dir)?;
+        Ok(RunLocks {
+            dir: dir.to_path_buf(),
+            config_key: state::path_key(config_path),
+            scope,
+            wait,
+        })
+    }
+
+    /// Held for a whole run when locking per configuration.
+    pub fn lock_config(&self) -> io::Result<Option<PidLock>> {
+        if self.scope != LockScope::Config {
+            return Ok(None);
+        }
+        let path = self.dir.join(format!("{}.lock", self.config_key));
+        PidLock::acquire(&path, self.wait).map(Some)
+    }
+
+    /// Held while a location is searched when locking per location. The lock
+    /// is shared by every configuration that searches the location.
+    pub fn lock_location(&self, location: &Path) -> io::Result<Option<PidLock>> {
+        if self.scope != LockScope::Location {
+            return Ok(None);
+        }
+        let path = self
+            .dir
+            .join(format!("location-{}.lock", state::path_key(location)));
+        PidLock::acquire(&path, self.wait).map(Some)
+    }
+
+    /// `lock_location` for each of `locations`, e.g. every location one
+    /// walk searches. Taken in the given order, which should be sorted so
+    /// that processes waiting for each other cannot deadlock.
+    pub fn lock_locations(&self, locations: &[&Path]) -> io::Result<Vec<PidLock>> {
+        let mut locks = Vec::new();
+        for location in locations {
+            locks.extend(self.lock_location(location)?);
+        }
+        Ok(locks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_lock_is_exclusive() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sparkle.pid");
+
+        let lock = PidLock::acquire(&path, false).unwrap();
+        let pid = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(pid.trim(), std::process::id().to_string());
+
+        let err = PidLock::acquire(&path, false).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(err.to_string().contains(pid.trim()));
+
+        drop(lock);
+        assert!(PidLock::acquire(&path, false).is_ok());
+    }
+
+    #[test]
+    fn test_stale_lock_is_replaced() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sparkle.lock");
+
+        // a file left behind by a process that died, with no flock held
+        fs::write(&path, format!("{}\n", libc::pid_t::MAX)).unwrap();
+
+        let lock = PidLock::acquire(&path, false).unwrap();
+        let pid = fs::read_to_string(&path).unwrap();
+        assert_eq!(pid.trim(), std::process::id().to_string());
+
+        // a held flock wins over a dead PID in the file
+        fs::write(&path, format!("{}\n", libc::pid_t::MAX)).unwrap();
+        let err = PidLock::acquire(&path, false).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::WouldBlock);
+        assert!(path.exists());
+        drop(lock);
+    }
+
+    #[test]
+    fn test_wait_blocks_until_released() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("sparkle.lock");
+
+        let held = PidLock::acquire(&path, false).unwrap();
+        let waiter = {
+            let path = path.clone();
+            std::thread::spawn(move || PidLock::acquire(&path, true).map(|_| ()))
+        };
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        assert!(!waiter.is_finished());
+        drop(held);
+        waiter.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_run_locks_scope() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = temp_dir.path().join("config.toml");
+
+        let locks = RunLocks::new(temp_dir.path(), &config, LockScope::Location, false).unwrap();
+        assert!(locks.lock_config().unwrap().is_none());
+        let location = locks.lock_location(Path::new("/tmp")).unwrap();
+        assert!(location.is_some());
+        assert!(locks.lock_location(Path::new("/tmp")).is_err());
+        assert!(locks.lock_location(Path::new("/var/tmp")).is_ok());
+    }
+}
@@ -1,21 +1,23 @@
-mod cli;
-mod config;
-mod content_info;
-mod crawl;
-mod file_detector;
-mod file_metadata;
-mod handlers;
-mod utils;
-use crate::cli::Cli;
-use crate::crawl::search_dir;
-use crate::file_metadata::FileContext;
-use clap::Parser;
-use file_metadata::FileMetadataError;
+use clap::{CommandFactory, Parser};
+use sparkle::check::Severity;
+use sparkle::cli::{Cli, Command, ConfigCommand};
+use sparkle::config;
+use sparkle::crawl::{Handled, group_by_tree, search_rules};
+use sparkle::format::Format;
+use sparkle::handlers::action::Outcome;
+use sparkle::lock::RunLocks;
+use std::path::Path;
 use tracing_subscriber::EnvFilter;
+use tracing_subscriber::filter::LevelFilter;
 
 fn main() {
     tracing_subscriber::fmt()
-        .with_env_filter(EnvFilter::from_default_env())
+        .with_env_filter(
+            // warnings include configurations that may not mean what they say
+            EnvFilter::builder()
+                .with_default_directive(LevelFilter::WARN.into())
+                .from_env_lossy(),
+        )
         .init();
 
     // TODO create default directories to move files into using cli
@@ -23,21 +25,131 @@ fn main() {
     // TODO rename when using a glob filter like name_contains, will overwrite the destionation
     // file with the last file from the filter applied.
     let cli = Cli::parse();
-    let config = config::Config::new(&cli.configuration).expect("Cannot parse config");
+    let Some(configuration) = cli.configuration.as_deref() else {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingRequiredArgument,
+                "the following required argument was not provided: --configuration <CONFIGURATION>",
+            )
+            .exit();
+    };
+    let path = Path::new(configuration);
+    let format = cli.format.unwrap_or_else(|| Format::from_path(path));
+    if let Some(Command::Config {
+        command: ConfigCommand::Convert { to, output },
+    }) = &cli.command
+    {
+        let Some(to) = to.or_else(|| output.as_deref().and_then(Format::from_extension)) else {
+            Cli::command()
+                .error(
+                    clap::error::ErrorKind::MissingRequiredArgument,
+                    "the following required argument was not provided: --to <TO>",
+                )
+                .exit();
+        };
+        if let Err(e) = convert(path, format, to, output.as_deref()) {
+            tracing::error!("Cannot convert {}: {}", configuration, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+    if let Some(Command::Check) = cli.command {
+        let findings = sparkle::check::check(path, format);
+        for finding in &findings {
+            println!("{}", finding);
+        }
+        if findings
+            .iter()
+            .any(|finding| finding.severity == Severity::Error)
+        {
+            std::process::exit(1);
+        }
+        println!("{}: ok", configuration);
+        return;
+    }
+    let mut config = match config::Config::load(path, format) {
+        Ok(config) => config,
+        Err(e) => {
+            tracing::error!(
+                "Cannot load {}: {}, `sparkle check` shows every problem",
+                configuration,
+                e
+            );
+            std::process::exit(1);
+        }
+    };
     tracing::debug!("config: {:?}", config);
-    let results: Vec<Result<FileContext, FileMetadataError>> = config
-        .rules
-        .iter()
-        .flat_map(|rule| {
-            rule.locations
-                .iter()
-                .flat_map(|d| match search_dir(d, &config, rule, cli.verbose) {
-                    Ok(file_contexts) => file_contexts.into_iter().map(Ok).collect::<Vec<_>>(),
-                    Err(e) => vec![Err(e)],
-                })
-        })
-        .collect();
-    tracing::info!(files = results.len(), "Files scanned");
+
+    if let Some(Command::Explain { path }) = &cli.command {
+        match sparkle::explain::explain(path, &config) {
+            Ok(explanation) => print!("{}", explanation),
+            Err(e) => {
+                tracing::error!("Cannot explain {}: {}", path.display(), e);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    let state_dir = cli
+        .state_dir
+        .clone()
+        .unwrap_or_else(sparkle::state::default_dir);
+    config
+        .cache_dir
+        .get_or_insert_with(|| state_dir.join("cache"));
+    let locks = match RunLocks::new(&state_dir, path, config.lock, cli.wait) {
+        Ok(locks) => locks,
+        Err(e) => {
+            tracing::error!("Cannot create {}: {}", state_dir.display(), e);
+            std::process::exit(1);
+        }
+    };
+
+    match cli.command {
+        Some(Command::Watch { settle }) => {
+            if let Err(e) = sparkle::watch::watch(&config, settle, &locks, cli.verbose) {
+                tracing::error!("Stopped watching: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Daemon) => {
+            if let Err(e) = sparkle::daemon::run(&config, path, &state_dir, &locks, cli.verbose) {
+                tracing::error!("Daemon stopped: {}", e);
+                std::process::exit(1);
+            }
+            return;
+        }
+        Some(Command::Check | Command::Config { .. } | Command::Explain { .. }) | None => {}
+    }
+
+    let _lock = match locks.lock_config() {
+        Ok(lock) => lock,
+        Err(e) => {
+            tracing::error!("Another run is in progress, skipping: {}", e);
+            std::process::exit(1);
+        }
+    };
+    // every directory tree is walked once for all rules with a location in it
+    let mut handled = Vec::new();
+    for tree in group_by_tree(&config.rules) {
+        let _locks = match locks.lock_locations(&tree.locations()) {
+            Ok(locks) => locks,
+            Err(e) => {
+                tracing::error!("Skipping {}: {}", tree.root.display(), e);
+                continue;
+            }
+        };
+        match search_rules(&tree, &config, cli.verbose) {
+            Ok(found) => handled.extend(found),
+            Err(e) => tracing::error!("Could not search {}: {}", tree.root.display(), e),
+        }
+    }
+    for h in &handled {
+        println!("{}", summary(h));
+    }
+    tracing::info!(files = handled.len(), "Files handled");
 
     //for f in results.iter() {
     //    let mime = f
@@ -56,3 +168,33 @@ fn main() {
     //}
     // next phase is rule phase
 }
+
+// Writes the converted configuration to `output`, or prints it
+fn convert(
+    path: &Path,
+    from: Format,
+    to: Format,
+    output: Option<&Path>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    let dir = path.parent().unwrap_or(Path::new(""));
+    let converted = sparkle::format::convert(&contents, dir, from, to)?;
+    match output {
+        Some(output) => std::fs::write(output, converted)?,
+        None => print!("{}", converted),
+    }
+    Ok(())
+}
+
+// Which rule handled a file and where it went, e.g.
+// `[pdfs] /home/user/Downloads/a.pdf -> /home/user/PDFs/a.pdf`
+fn summary(handled: &Handled) -> String {
+    let path = handled.context.path.display();
+    match &handled.outcome {
+        Outcome::At(to) if *to == handled.context.path => {
+            format!("[{}] {}", handled.rule.name, path)
+        }
+        Outcome::At(to) => format!("[{}] {} -> {}", handled.rule.name, path, to.display()),
+        Outcome::Consumed => format!("[{}] {} (deleted)", handled.rule.name, path),
+    }
+}
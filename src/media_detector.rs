@@ -0,0 +1,97 @@
+use crate::content_info::MediaInfo;
+use chrono::{FixedOffset, Local, NaiveDate, TimeZone};
+use exif::{In, Reader, Tag, Value};
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Reads EXIF data from an image. Returns `None` when the file has none.
+pub fn read_media_info(path: &Path) -> Option<MediaInfo> {
+    let file = File::open(path).ok()?;
+    let exif = Reader::new()
+        .read_from_container(&mut BufReader::new(file))
+        .ok()?;
+
+    let text = |tag: Tag| {
+        exif.get_field(tag, In::PRIMARY).map(|field| {
+            field
+                .display_value()
+                .to_string()
+                .trim_matches('"')
+                .to_string()
+        })
+    };
+    let dimension = |tag: Tag| {
+        exif.get_field(tag, In::PRIMARY)
+            .and_then(|field| field.value.get_uint(0))
+    };
+
+    let date_taken = exif
+        .get_field(Tag::DateTimeOriginal, In::PRIMARY)
+        .and_then(|field| match &field.value {
+            Value::Ascii(values) => values.first(),
+            _ => None,
+        })
+        .and_then(|ascii| {
+            let mut taken = exif::DateTime::from_ascii(ascii).ok()?;
+            if let Some(offset) = exif.get_field(Tag::OffsetTimeOriginal, In::PRIMARY)
+                && let Value::Ascii(values) = &offset.value
+                && let Some(value) = values.first()
+            {
+                let _ = taken.parse_offset(value);
+            }
+            exif_time(&taken)
+        });
+
+    Some(MediaInfo {
+        width: dimension(Tag::PixelXDimension),
+        height: dimension(Tag::PixelYDimension),
+        duration: None,
+        date_taken,
+        camera_make: text(Tag::Make),
+        camera_model: text(Tag::Model),
+        gps_coordinates: None,
+    })
+}
+
+// EXIF dates carry no time zone unless OffsetTimeOriginal is present; cameras
+// record local time, so fall back to the local zone.
+fn exif_time(taken: &exif::DateTime) -> Option<SystemTime> {
+    let naive = NaiveDate::from_ymd_opt(taken.year as i32, taken.month as u32, taken.day as u32)?
+        .and_hms_opt(taken.hour as u32, taken.minute as u32, taken.second as u32)?;
+    match taken.offset {
+        Some(minutes) => FixedOffset::east_opt(minutes as i32 * 60)?
+            .from_local_datetime(&naive)
+            .single()
+            .map(SystemTime::from),
+        None => Local
+            .from_local_datetime(&naive)
+            .earliest()
+            .map(SystemTime::from),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    #[test]
+    fn test_exif_time_with_offset() {
+        let mut taken = exif::DateTime::from_ascii(b"2021:06:01 12:00:00").unwrap();
+        taken.parse_offset(b"+02:00").unwrap();
+
+        let expected = UNIX_EPOCH + Duration::from_secs(1_622_541_600);
+        assert_eq!(exif_time(&taken), Some(expected));
+    }
+
+    #[test]
+    fn test_read_media_info_without_exif() {
+        use tempfile::NamedTempFile;
+
+        let file = NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), "not an image").unwrap();
+        assert!(read_media_info(file.path()).is_none());
+    }
+}
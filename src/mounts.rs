@@ -0,0 +1,109 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+const MOUNTINFO: &str = "/proc/self/mountinfo";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mount {
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+}
+
+/// The mounts visible to this process, from `/proc/self/mountinfo`.
+#[derive(Debug, Default)]
+pub struct MountTable {
+    mounts: Vec<Mount>,
+}
+
+impl MountTable {
+    pub fn load() -> io::Result<MountTable> {
+        Ok(MountTable::parse(&fs::read_to_string(MOUNTINFO)?))
+    }
+
+    // Lines look like
+    // `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw,errors=continue`
+    // with a variable number of optional fields before the `-`.
+    fn parse(mountinfo: &str) -> MountTable {
+        let mounts = mountinfo
+            .lines()
+            .filter_map(|line| {
+                let (mount, filesystem) = line.split_once(" - ")?;
+                let mount_point = mount.split(' ').nth(4)?;
+                let fs_type = filesystem.split(' ').next()?;
+                Some(Mount {
+                    mount_point: PathBuf::from(unescape(mount_point)),
+                    fs_type: fs_type.to_string(),
+                })
+            })
+            .collect();
+        MountTable { mounts }
+    }
+
+    /// Filesystem type of the mount `path` lives on. Later mounts on the same
+    /// point hide earlier ones, as they do in the kernel.
+    pub fn fs_type(&self, path: &Path) -> Option<&str> {
+        let path = fs::canonicalize(path).ok()?;
+        self.mounts
+            .iter()
+            .enumerate()
+            .filter(|(_, mount)| path.starts_with(&mount.mount_point))
+            .max_by_key(|(index, mount)| (mount.mount_point.components().count(), *index))
+            .map(|(_, mount)| mount.fs_type.as_str())
+    }
+}
+
+// Spaces, tabs, newlines and backslashes in paths are written as octal
+// escapes such as `\040`
+fn unescape(field: &str) -> String {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(octal) = field.get(i + 1..i + 4)
+            && let Ok(byte) = u8::from_str_radix(octal, 8)
+        {
+            out.push(byte);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SAMPLE: &str = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+35 22 0:40 / /home/user/nas rw,relatime shared:20 - nfs4 server:/export rw,vers=4.2
+36 35 0:41 / /home/user/nas/My\\040Files rw,nosuid - fuse.sshfs user@host:/ rw
+";
+
+    #[test]
+    fn test_parse_mountinfo() {
+        let table = MountTable::parse(SAMPLE);
+        assert_eq!(table.mounts.len(), 3);
+        assert_eq!(
+            table.mounts[2],
+            Mount {
+                mount_point: PathBuf::from("/home/user/nas/My Files"),
+                fs_type: "fuse.sshfs".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_fs_type_of_path() {
+        let table = MountTable::parse(SAMPLE);
+        assert_eq!(table.fs_type(Path::new("/")), Some("ext4"));
+
+        let table = MountTable::load().unwrap();
+        assert!(table.fs_type(Path::new("/")).is_some());
+        assert_eq!(table.fs_type(Path::new("/no/such/path")), None);
+    }
+}
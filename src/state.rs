@@ -0,0 +1,109 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Where run state is kept: `$XDG_STATE_HOME/sparkle`, falling back to
+/// `~/.local/state/sparkle`.
+pub fn default_dir() -> PathBuf {
+    let base = std::env::var_os("XDG_STATE_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".local/state")))
+        .unwrap_or_else(|| PathBuf::from("."));
+    base.join("sparkle")
+}
+
+/// A file name stem unique to one file or directory, e.g.
+/// `config-3b1e3ff8a9d5c7e2`, so state of different configs never mixes.
+pub fn path_key(path: &Path) -> String {
+    let path = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| "config".to_string());
+    format!(
+        "{}-{:016x}",
+        stem,
+        fnv1a(path.as_os_str().as_encoded_bytes())
+    )
+}
+
+// FNV-1a, which unlike std's hasher stays the same across Rust releases
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// When each rule last ran, by rule name.
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct LastRuns {
+    // seconds since the Unix epoch
+    #[serde(default)]
+    last_run: BTreeMap<String, u64>,
+}
+
+impl LastRuns {
+    /// A missing file means nothing ran yet.
+    pub fn load(path: &Path) -> io::Result<LastRuns> {
+        match fs::read_to_string(path) {
+            Ok(contents) => toml::from_str(&contents).map_err(io::Error::other),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(LastRuns::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Replaces the file atomically, so a crash never leaves half a state file.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let contents = toml::to_string(self).map_err(io::Error::other)?;
+        let temp = path.with_extension("tmp");
+        fs::write(&temp, contents)?;
+        fs::rename(temp, path)
+    }
+
+    pub fn get(&self, rule: &str) -> Option<SystemTime> {
+        self.last_run
+            .get(rule)
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(*secs))
+    }
+
+    pub fn set(&mut self, rule: &str, time: SystemTime) {
+        let secs = time
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.last_run.insert(rule.to_string(), secs);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_last_runs_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("config.state");
+        assert_eq!(LastRuns::load(&path).unwrap(), LastRuns::default());
+
+        let time = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+        let mut runs = LastRuns::default();
+        runs.set("Clean old logs", time);
+        runs.save(&path).unwrap();
+
+        let loaded = LastRuns::load(&path).unwrap();
+        assert_eq!(loaded.get("Clean old logs"), Some(time));
+        assert_eq!(loaded.get("other"), None);
+    }
+
+    #[test]
+    fn test_path_key() {
+        let key = path_key(Path::new("/etc/sparkle/downloads.toml"));
+        assert!(key.starts_with("downloads-"));
+        assert_ne!(key, path_key(Path::new("/etc/other/downloads.toml")));
+    }
+}
@@ -0,0 +1,263 @@
+use chardetng::{EncodingDetector, Iso2022JpDetection, Utf8Detection};
+use encoding_rs::{Encoding, UTF_8, UTF_16BE, UTF_16LE};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use whatlang::Lang;
+
+// How much of a file we look at when sniffing encoding and language
+pub const SAMPLE_SIZE: u64 = 64 * 1024;
+
+// Reported for text that is pure 7-bit ASCII. It is also valid UTF-8.
+pub const ASCII: &str = "ASCII";
+
+/// Reads up to `SAMPLE_SIZE` bytes from the start of the file.
+pub fn read_sample(path: &Path) -> io::Result<Vec<u8>> {
+    let mut sample = Vec::new();
+    File::open(path)?
+        .take(SAMPLE_SIZE)
+        .read_to_end(&mut sample)?;
+    Ok(sample)
+}
+
+/// Guesses the encoding of `bytes`. Returns `None` when the data looks binary.
+///
+/// `complete` should be true when `bytes` holds the whole file, so a multi-byte
+/// sequence cut off at the end of a sample is not mistaken for invalid UTF-8.
+pub fn detect_encoding(bytes: &[u8], complete: bool) -> Option<&'static Encoding> {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return Some(encoding);
+    }
+
+    if let Some(encoding) = guess_utf16(bytes) {
+        return Some(encoding);
+    }
+
+    // NUL bytes outside of UTF-16 mean we are looking at binary data
+    if bytes.contains(&0) {
+        return None;
+    }
+
+    match std::str::from_utf8(bytes) {
+        Ok(_) => return Some(UTF_8),
+        // error_len() is None when the input ends in the middle of a character
+        Err(e) if !complete && e.error_len().is_none() => return Some(UTF_8),
+        Err(_) => {}
+    }
+
+    let mut detector = EncodingDetector::new(Iso2022JpDetection::Deny);
+    detector.feed(bytes, complete);
+    Some(detector.guess(None, Utf8Detection::Deny))
+}
+
+/// Returns the name reported for text in `encoding`, e.g. "UTF-8", "UTF-16LE",
+/// "windows-1252", or "ASCII" when UTF-8 `bytes` never leave the ASCII range.
+pub fn encoding_name(encoding: &'static Encoding, bytes: &[u8]) -> String {
+    if encoding == UTF_8 && bytes.is_ascii() {
+        return ASCII.to_string();
+    }
+    encoding.name().to_string()
+}
+
+// UTF-16 without a BOM shows up as a NUL in every other byte for Latin text
+fn guess_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let pairs = bytes.len() / 2;
+    if pairs < 2 {
+        return None;
+    }
+    let even_nuls = bytes.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd_nuls = bytes.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+
+    // require a strong skew so binary data with scattered NULs is not matched
+    if even_nuls * 10 >= pairs * 4 && odd_nuls * 10 < pairs {
+        Some(UTF_16BE)
+    } else if odd_nuls * 10 >= pairs * 4 && even_nuls * 10 < pairs {
+        Some(UTF_16LE)
+    } else {
+        None
+    }
+}
+
+/// Identifies the language of `text`, returning its ISO 639-3 code ("eng", "deu").
+/// Only reliable guesses are returned.
+pub fn detect_language(text: &str) -> Option<String> {
+    let info = whatlang::detect(text)?;
+    if info.is_reliable() {
+        Some(info.lang().code().to_string())
+    } else {
+        None
+    }
+}
+
+/// Checks a detected ISO 639-3 code against a user supplied language, which may
+/// be the code itself or the English name ("eng" or "English").
+pub fn language_matches(detected: &str, wanted: &str) -> bool {
+    if detected.eq_ignore_ascii_case(wanted) {
+        return true;
+    }
+    Lang::from_code(detected)
+        .map(|lang| lang.eng_name().eq_ignore_ascii_case(wanted))
+        .unwrap_or(false)
+}
+
+/// Checks a detected encoding name against a user supplied one. Dashes and case
+/// are ignored, and ASCII files also count as UTF-8.
+pub fn encoding_matches(detected: &str, wanted: &str) -> bool {
+    let normalize = |s: &str| s.replace(['-', '_'], "").to_lowercase();
+    let (detected, wanted) = (normalize(detected), normalize(wanted));
+    detected == wanted || (detected == "ascii" && wanted == "utf8")
+}
+
+/// Rewrites the file as UTF-8 without a BOM. `encoding` is the name a filter
+/// detected, e.g. "windows-1252"; without one the whole file is looked at.
+/// Returns `Ok(false)` when the file was already UTF-8 and nothing had to be
+/// written. Bytes that are not valid in the encoding are an error and leave
+/// the file as it was, and the new contents replace it atomically.
+pub fn transcode_to_utf8(path: &Path, encoding: Option<&str>) -> io::Result<bool> {
+    let bytes = fs::read(path)?;
+    let encoding = match encoding {
+        // ASCII is a label of windows-1252 to encoding_rs
+        Some(ASCII) => Some(UTF_8),
+        Some(name) => Encoding::for_label(name.as_bytes()),
+        None => detect_encoding(&bytes, true),
+    }
+    .ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("'{}' does not look like a text file", path.display()),
+        )
+    })?;
+
+    let has_bom = Encoding::for_bom(&bytes).is_some();
+    if encoding == UTF_8 && !has_bom && std::str::from_utf8(&bytes).is_ok() {
+        return Ok(false);
+    }
+
+    // decode() strips the BOM for us
+    let (text, used, had_errors) = encoding.decode(&bytes);
+    if had_errors {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "'{}' is not valid {}, leaving it as it is",
+                path.display(),
+                used.name()
+            ),
+        ));
+    }
+    replace(path, text.as_bytes())?;
+    Ok(true)
+}
+
+// Writes next to the file and renames over it, so a crash leaves either the
+// old or the new contents. The permissions are kept.
+fn replace(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = path.with_file_name(format!(".{}.sparkle-tmp", name));
+    let result = (|| {
+        let mut file = File::create(&temp)?;
+        file.write_all(contents)?;
+        file.set_permissions(fs::metadata(path)?.permissions())?;
+        file.sync_all()?;
+        fs::rename(&temp, path)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_encoding_bom() {
+        assert_eq!(detect_encoding(b"\xEF\xBB\xBFhello", true), Some(UTF_8));
+        assert_eq!(detect_encoding(b"\xFF\xFEh\0i\0", true), Some(UTF_16LE));
+        assert_eq!(detect_encoding(b"\xFE\xFF\0h\0i", true), Some(UTF_16BE));
+    }
+
+    #[test]
+    fn test_detect_encoding_utf16_without_bom() {
+        let le: Vec<u8> = "hello world"
+            .encode_utf16()
+            .flat_map(|c| c.to_le_bytes())
+            .collect();
+        let be: Vec<u8> = "hello world"
+            .encode_utf16()
+            .flat_map(|c| c.to_be_bytes())
+            .collect();
+        assert_eq!(detect_encoding(&le, true), Some(UTF_16LE));
+        assert_eq!(detect_encoding(&be, true), Some(UTF_16BE));
+    }
+
+    #[test]
+    fn test_encoding_name() {
+        let name = |bytes: &[u8]| detect_encoding(bytes, true).map(|e| encoding_name(e, bytes));
+        assert_eq!(name(b"plain ascii").as_deref(), Some("ASCII"));
+        assert_eq!(name("caf\u{e9}".as_bytes()).as_deref(), Some("UTF-8"));
+        assert_eq!(
+            name(b"caf\xE9 cr\xE8me br\xFBl\xE9e").as_deref(),
+            Some("windows-1252")
+        );
+        assert_eq!(name(b"\x7FELF\0\0\x01\0"), None);
+    }
+
+    #[test]
+    fn test_truncated_utf8_sample() {
+        // last character is cut in half
+        let bytes = &"caf\u{e9}".as_bytes()[..4];
+        assert_eq!(detect_encoding(bytes, false), Some(UTF_8));
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let english = "The quick brown fox jumps over the lazy dog while the farmer \
+                       watches from the porch and drinks his morning coffee.";
+        let german = "Der schnelle braune Fuchs springt über den faulen Hund, während \
+                      der Bauer von der Veranda aus zuschaut und seinen Kaffee trinkt.";
+        assert_eq!(detect_language(english).as_deref(), Some("eng"));
+        assert_eq!(detect_language(german).as_deref(), Some("deu"));
+    }
+
+    #[test]
+    fn test_language_and_encoding_matches() {
+        assert!(language_matches("eng", "ENG"));
+        assert!(language_matches("deu", "German"));
+        assert!(!language_matches("deu", "eng"));
+        assert!(encoding_matches("UTF-8", "utf8"));
+        assert!(encoding_matches("ASCII", "utf-8"));
+        assert!(encoding_matches("windows-1252", "Windows_1252"));
+        assert!(!encoding_matches("UTF-8", "ascii"));
+    }
+
+    #[test]
+    fn test_transcode_to_utf8() {
+        use tempfile::NamedTempFile;
+
+        let file = NamedTempFile::new().unwrap();
+        fs::write(file.path(), b"caf\xE9 cr\xE8me").unwrap();
+        assert!(transcode_to_utf8(file.path(), Some("windows-1252")).unwrap());
+        assert_eq!(fs::read_to_string(file.path()).unwrap(), "café crème");
+
+        // already UTF-8, nothing to do
+        assert!(!transcode_to_utf8(file.path(), None).unwrap());
+    }
+
+    #[test]
+    fn test_transcode_keeps_undecodable_files() {
+        use tempfile::TempDir;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("notes.txt");
+        // not valid UTF-16LE: an unpaired surrogate
+        let bytes = b"\xFF\xFEh\0i\0\x00\xD8";
+        fs::write(&path, bytes).unwrap();
+
+        assert!(transcode_to_utf8(&path, Some("UTF-16LE")).is_err());
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+        // no temporary file is left behind
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 1);
+    }
+}
@@ -1,63 +1,172 @@
-use std::path::Path;
+use crate::config::CompressionFormat;
+use flate2::Compression;
+use flate2::write::GzEncoder;
+use std::path::{Path, PathBuf};
 use std::{
     fs,
-    io::{self},
+    io::{self, Write},
 };
 
-pub fn move_file(source_path: &Path, destination_path: &Path) -> std::io::Result<()> {
-    tracing::debug!("Moving file {:?} to {:?}", source_path, destination_path);
+/// Moves a file or directory into `destination_path` and returns its new
+/// path. Falls back to copy and delete when the destination is on another
+/// filesystem.
+pub fn move_file(source_path: &Path, destination_path: &Path) -> std::io::Result<PathBuf> {
+    tracing::debug!("Moving {:?} to {:?}", source_path, destination_path);
     // TODO check if destination_path exists first
     let file_name = source_path
         .file_name()
         .ok_or_else(|| std::io::Error::other("Source has no filename"))?;
 
     let destination = destination_path.join(file_name);
-    let source_meta = fs::metadata(source_path)?;
-    if source_meta.is_dir() {
-        return Err(io::Error::new(
-            io::ErrorKind::IsADirectory,
-            format!(
-                "Cannot move a directory named '{}'",
-                source_path.to_string_lossy()
-            ),
-        ));
-    }
-
-    //let dest_meta = fs::metadata(destination_path)?;
-    //if dest_meta.is_dir() {
-    //    // This is a file move, not a directory move, so we rename the file and remove the containing directory.
-    //    fs::remove_dir_all(destination_path)?;
-    //    return fs::rename(source_path, destination_path);
-    //}
-
-    // Both are files, so let's do the move.
-    fs::rename(source_path, destination)
+    match fs::rename(source_path, &destination) {
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            copy_recursive(source_path, &destination)?;
+            remove_path(source_path)?;
+        }
+        result => result?,
+    }
+    Ok(destination)
 }
 
+/// Copies a file or a whole directory into `destination_path`. Returns the
+/// number of bytes copied.
 pub fn copy_file(source_path: &Path, destination_path: &Path) -> std::io::Result<u64> {
     let file_name = source_path
         .file_name()
         .ok_or_else(|| std::io::Error::other("Source has no filename"))?;
 
     let destination = destination_path.join(file_name);
+    copy_recursive(source_path, &destination)
+}
+
+// Symlinks are copied as links rather than followed
+fn copy_recursive(source: &Path, destination: &Path) -> io::Result<u64> {
+    let metadata = fs::symlink_metadata(source)?;
+    if metadata.is_symlink() {
+        std::os::unix::fs::symlink(fs::read_link(source)?, destination)?;
+        return Ok(0);
+    }
+    if !metadata.is_dir() {
+        return fs::copy(source, destination);
+    }
+
+    fs::create_dir(destination)?;
+    let mut copied = 0;
+    for entry in fs::read_dir(source)? {
+        let entry = entry?;
+        copied += copy_recursive(&entry.path(), &destination.join(entry.file_name()))?;
+    }
+    fs::set_permissions(destination, metadata.permissions())?;
+    Ok(copied)
+}
+
+/// Deletes a file, symlink or directory with everything below it.
+pub fn remove_path(path: &Path) -> io::Result<()> {
+    if fs::symlink_metadata(path)?.is_dir() {
+        fs::remove_dir_all(path)
+    } else {
+        fs::remove_file(path)
+    }
+}
+
+/// Packs a file or directory into `<name>.<format>` inside `destination_path`
+/// and returns the archive's path. Existing archives are never overwritten.
+pub fn archive(
+    source_path: &Path,
+    destination_path: &Path,
+    format: CompressionFormat,
+) -> io::Result<PathBuf> {
+    let file_name = source_path
+        .file_name()
+        .ok_or_else(|| std::io::Error::other("Source has no filename"))?;
+
+    let mut archive_name = file_name.to_os_string();
+    archive_name.push(".");
+    archive_name.push(format.extension());
+    let destination = destination_path.join(archive_name);
+
+    let file = fs::File::create_new(&destination)?;
+    let result = match format {
+        CompressionFormat::Tar => write_tar(file, source_path, file_name).map(|_| ()),
+        CompressionFormat::TarGz => write_tar(
+            GzEncoder::new(file, Compression::default()),
+            source_path,
+            file_name,
+        )
+        .and_then(|encoder| encoder.finish().map(|_| ())),
+    };
+    if let Err(e) = result {
+        // don't leave a truncated archive behind
+        let _ = fs::remove_file(&destination);
+        return Err(e);
+    }
+    Ok(destination)
+}
+
+fn write_tar<W: Write>(writer: W, source_path: &Path, name: &std::ffi::OsStr) -> io::Result<W> {
+    let mut builder = tar::Builder::new(writer);
+    builder.follow_symlinks(false);
+    if fs::symlink_metadata(source_path)?.is_dir() {
+        builder.append_dir_all(name, source_path)?;
+    } else {
+        builder.append_path_with_name(source_path, name)?;
+    }
+    builder.into_inner()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn project(root: &Path) -> PathBuf {
+        let project = root.join("project");
+        fs::create_dir_all(project.join("src")).unwrap();
+        fs::write(project.join("README"), "hello").unwrap();
+        fs::write(project.join("src").join("main.rs"), "fn main() {}").unwrap();
+        project
+    }
+
+    #[test]
+    fn test_copy_and_move_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = project(temp_dir.path());
+        let backup = temp_dir.path().join("backup");
+        let moved = temp_dir.path().join("moved");
+        fs::create_dir(&backup).unwrap();
+        fs::create_dir(&moved).unwrap();
 
-    let source_meta = fs::metadata(source_path)?;
-    if source_meta.is_dir() {
-        return Err(io::Error::new(
-            io::ErrorKind::IsADirectory,
-            format!(
-                "Cannot move a directory named '{}'",
-                source_path.to_string_lossy()
-            ),
-        ));
-    }
-
-    //let dest_meta = fs::metadata(destination_path)?;
-    //if dest_meta.is_dir() {
-    //    // This is a file move, not a directory move, so we rename the file and remove the containing directory.
-    //    fs::remove_dir_all(destination_path)?;
-    //    return fs::copy(source_path, destination_path);
-    //}
-
-    fs::copy(source_path, destination)
+        assert_eq!(copy_file(&source, &backup).unwrap(), 17);
+        assert!(backup.join("project/src/main.rs").is_file());
+        assert!(source.exists());
+
+        assert_eq!(move_file(&source, &moved).unwrap(), moved.join("project"));
+        assert!(moved.join("project/README").is_file());
+        assert!(!source.exists());
+
+        remove_path(&moved.join("project")).unwrap();
+        assert!(!moved.join("project").exists());
+    }
+
+    #[test]
+    fn test_archive_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = project(temp_dir.path());
+
+        let path = archive(&source, temp_dir.path(), CompressionFormat::TarGz).unwrap();
+        assert_eq!(path, temp_dir.path().join("project.tar.gz"));
+
+        let decoder = flate2::read::GzDecoder::new(fs::File::open(&path).unwrap());
+        let names: Vec<String> = tar::Archive::new(decoder)
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        assert!(names.contains(&"project/README".to_string()));
+        assert!(names.contains(&"project/src/main.rs".to_string()));
+
+        // an existing archive is left alone
+        assert!(archive(&source, temp_dir.path(), CompressionFormat::TarGz).is_err());
+        assert!(path.is_file());
+    }
 }
@@ -0,0 +1,355 @@
+use crate::config::Config;
+use crate::crawl::{entered_dirs, group_by_tree, search_path_rules, search_rules};
+use crate::lock::RunLocks;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// Watches the locations of every rule and runs the rules on files once they
+/// are fully written. Only returns when watching fails.
+pub fn watch(config: &Config, settle: Duration, locks: &RunLocks, quiet: bool) -> io::Result<()> {
+    let mut watcher = Watcher::new(config, settle, locks, quiet)?;
+    watcher.watch_locations()?;
+    tracing::info!("Watching {} directories", watcher.dirs.len());
+    loop {
+        watcher.step()?;
+    }
+}
+
+struct Watcher<'a> {
+    config: &'a Config,
+    locks: &'a RunLocks,
+    inotify: Inotify,
+    // every watched directory
+    dirs: HashMap<WatchDescriptor, PathBuf>,
+    // changed paths waiting for writes to stop
+    pending: HashMap<PathBuf, Pending>,
+    settle: Duration,
+    quiet: bool,
+}
+
+struct Pending {
+    due: Instant,
+    size: Option<u64>,
+}
+
+impl<'a> Watcher<'a> {
+    fn new(
+        config: &'a Config,
+        settle: Duration,
+        locks: &'a RunLocks,
+        quiet: bool,
+    ) -> io::Result<Watcher<'a>> {
+        Ok(Watcher {
+            config,
+            locks,
+            inotify: Inotify::init()?,
+            dirs: HashMap::new(),
+            pending: HashMap::new(),
+            settle,
+            quiet,
+        })
+    }
+
+    fn watch_locations(&mut self) -> io::Result<()> {
+        for tree in group_by_tree(&self.config.rules) {
+            self.add_tree(tree.root);
+        }
+        if self.dirs.is_empty() {
+            return Err(io::Error::other(
+                "none of the rule locations can be watched",
+            ));
+        }
+        Ok(())
+    }
+
+    // Watches `dir` and the directories below it that a search would enter,
+    // so excluded and ignored directories or other filesystems cost no watches
+    fn add_tree(&mut self, dir: &Path) {
+        let mask = WatchMask::CLOSE_WRITE
+            | WatchMask::MOVED_TO
+            | WatchMask::CREATE
+            | WatchMask::MODIFY
+            | WatchMask::ONLYDIR
+            | WatchMask::DONT_FOLLOW;
+        for tree in group_by_tree(&self.config.rules) {
+            let dirs = match entered_dirs(dir, &tree, self.config) {
+                Ok(dirs) => dirs,
+                Err(e) => {
+                    tracing::error!("Could not watch {}: {}", dir.display(), e);
+                    continue;
+                }
+            };
+            for dir in dirs {
+                match self.inotify.watches().add(&dir, mask) {
+                    Ok(wd) => {
+                        self.dirs.insert(wd, dir);
+                    }
+                    // ENOSPC means fs.inotify.max_user_watches is too low
+                    Err(e) => tracing::error!("Could not watch {}: {}", dir.display(), e),
+                }
+            }
+        }
+    }
+
+    // Waits for events or the next pending path to settle, then handles both
+    fn step(&mut self) -> io::Result<()> {
+        self.wait()?;
+        self.read_events()?;
+        self.run_settled();
+        Ok(())
+    }
+
+    fn wait(&self) -> io::Result<()> {
+        let timeout = match self.pending.values().map(|p| p.due).min() {
+            Some(due) => {
+                let left = due.saturating_duration_since(Instant::now());
+                // round up so we never wake just before a path is due
+                left.as_millis().min(i32::MAX as u128) as i32 + 1
+            }
+            None => -1,
+        };
+        let mut fd = libc::pollfd {
+            fd: self.inotify.as_raw_fd(),
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: fd points to a single valid pollfd for the whole call
+        if unsafe { libc::poll(&mut fd, 1, timeout) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() != io::ErrorKind::Interrupted {
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    fn read_events(&mut self) -> io::Result<()> {
+        let mut buffer = [0; 4096];
+        loop {
+            let events = match self.inotify.read_events(&mut buffer) {
+                Ok(events) => events,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let events: Vec<_> = events.map(|event| event.to_owned()).collect();
+            for event in events {
+                if event.mask.contains(EventMask::Q_OVERFLOW) {
+                    tracing::warn!("Missed file events, searching all locations again");
+                    self.rescan();
+                    continue;
+                }
+                if event.mask.contains(EventMask::IGNORED) {
+                    // the directory was deleted or unmounted
+                    self.dirs.remove(&event.wd);
+                    continue;
+                }
+                let (Some(dir), Some(name)) = (self.dirs.get(&event.wd), event.name) else {
+                    continue;
+                };
+                let path = dir.join(name);
+                if event.mask.contains(EventMask::ISDIR) {
+                    self.add_tree(&path);
+                }
+                self.changed(path);
+            }
+        }
+    }
+
+    // Starts or restarts the settle time of a path. Changes inside a pending
+    // directory only push the directory back, its search will cover them.
+    fn changed(&mut self, path: PathBuf) {
+        let due = Instant::now() + self.settle;
+        for ancestor in path.ancestors().skip(1) {
+            if let Some(pending) = self.pending.get_mut(ancestor) {
+                pending.due = due;
+                return;
+            }
+        }
+        let size = fs::symlink_metadata(&path).ok().map(|m| m.len());
+        self.pending.insert(path, Pending { due, size });
+    }
+
+    fn run_settled(&mut self) {
+        let now = Instant::now();
+        let due: Vec<PathBuf> = self
+            .pending
+            .iter()
+            .filter(|(_, pending)| pending.due <= now)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in due {
+            let size = fs::symlink_metadata(&path).ok().map(|m| m.len());
+            let Some(pending) = self.pending.get_mut(&path) else {
+                continue;
+            };
+            // still growing without events, e.g. written through mmap
+            if size.is_some() && size != pending.size {
+                pending.size = size;
+                pending.due = now + self.settle;
+                continue;
+            }
+            if size.is_none() || self.run_rules(&path) {
+                self.pending.remove(&path);
+            } else if let Some(pending) = self.pending.get_mut(&path) {
+                pending.due = now + self.settle;
+            }
+        }
+    }
+
+    // Runs every rule with a location that contains `path`, together for
+    // each directory tree. Returns false when a lock was busy, so the path is tried
+    // again later.
+    fn run_rules(&self, path: &Path) -> bool {
+        let _lock = match self.locks.lock_config() {
+            Ok(lock) => lock,
+            Err(e) => {
+                tracing::info!("Postponing {}: {}", path.display(), e);
+                return false;
+            }
+        };
+        let mut done = true;
+        for tree in group_by_tree(&self.config.rules) {
+            if !path.starts_with(tree.root) {
+                continue;
+            }
+            let _locks = match self.locks.lock_locations(&tree.locations()) {
+                Ok(locks) => locks,
+                Err(e) => {
+                    tracing::info!("Postponing {}: {}", path.display(), e);
+                    done = false;
+                    continue;
+                }
+            };
+            match search_path_rules(path, &tree, self.config, self.quiet) {
+                Ok(handled) => {
+                    for h in handled {
+                        tracing::info!(rule = h.rule.name, "Handled {}", h.context.path.display());
+                    }
+                }
+                Err(e) => tracing::error!("Rules failed on {}: {}", path.display(), e),
+            }
+        }
+        done
+    }
+
+    fn rescan(&mut self) {
+        self.pending.clear();
+        let _lock = match self.locks.lock_config() {
+            Ok(lock) => lock,
+            Err(e) => {
+                tracing::warn!("Skipping search of all locations: {}", e);
+                return;
+            }
+        };
+        for tree in group_by_tree(&self.config.rules) {
+            let _locks = match self.locks.lock_locations(&tree.locations()) {
+                Ok(locks) => locks,
+                Err(e) => {
+                    tracing::warn!("Skipping {}: {}", tree.root.display(), e);
+                    continue;
+                }
+            };
+            if let Err(e) = search_rules(&tree, self.config, self.quiet) {
+                tracing::error!("Could not search {}: {}", tree.root.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::LockScope;
+    use std::io::Write;
+    use tempfile::{NamedTempFile, TempDir};
+
+    #[test]
+    fn test_watch_runs_rules_on_new_files() {
+        let location = TempDir::new().unwrap();
+        let destination = TempDir::new().unwrap();
+        let toml_content = format!(
+            r#"
+[[rules]]
+name = "pdfs"
+locations = ["{}"]
+subfolders = true
+filters = [{{ extension = "pdf" }}]
+actions = [{{ move = "{}" }}]
+"#,
+            location.path().display(),
+            destination.path().display()
+        );
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        let state_dir = TempDir::new().unwrap();
+        let locks =
+            RunLocks::new(state_dir.path(), temp_file.path(), LockScope::Config, false).unwrap();
+        let mut watcher = Watcher::new(&config, Duration::from_millis(50), &locks, true).unwrap();
+        watcher.watch_locations().unwrap();
+
+        // a directory created after the watch started is picked up too
+        let nested = location.path().join("nested");
+        fs::create_dir(&nested).unwrap();
+        watcher.step().unwrap();
+        fs::write(nested.join("report.pdf"), "pdf").unwrap();
+        fs::write(location.path().join("notes.txt"), "text").unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !destination.path().join("report.pdf").exists() && Instant::now() < deadline {
+            watcher.step().unwrap();
+        }
+        assert!(destination.path().join("report.pdf").exists());
+        assert!(location.path().join("notes.txt").exists());
+    }
+
+    #[test]
+    fn test_excluded_dirs_are_not_watched() {
+        let location = TempDir::new().unwrap();
+        let root = location.path();
+        fs::create_dir_all(root.join("node_modules").join("pkg")).unwrap();
+        fs::create_dir(root.join("src")).unwrap();
+        let toml_content = format!(
+            r#"
+[[rules]]
+name = "pdfs"
+locations = ["{}"]
+subfolders = true
+exclude = ["node_modules", "target"]
+filters = [{{ extension = "pdf" }}]
+actions = [{{ echo = "pdf" }}]
+"#,
+            root.display()
+        );
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", toml_content).unwrap();
+        let config = Config::new(temp_file.path().to_str().unwrap()).unwrap();
+
+        let state_dir = TempDir::new().unwrap();
+        let locks =
+            RunLocks::new(state_dir.path(), temp_file.path(), LockScope::Config, false).unwrap();
+        let mut watcher = Watcher::new(&config, Duration::from_secs(60), &locks, true).unwrap();
+        watcher.watch_locations().unwrap();
+        let watched = |watcher: &Watcher| {
+            let mut dirs: Vec<PathBuf> = watcher.dirs.values().cloned().collect();
+            dirs.sort();
+            dirs
+        };
+        assert_eq!(watched(&watcher), [root.to_path_buf(), root.join("src")]);
+
+        // directories created later are only watched when not excluded either
+        fs::create_dir(root.join("target")).unwrap();
+        fs::create_dir(root.join("docs")).unwrap();
+        watcher.step().unwrap();
+        assert_eq!(
+            watched(&watcher),
+            [root.to_path_buf(), root.join("docs"), root.join("src")]
+        );
+    }
+}
@@ -1,7 +1,8 @@
 use sparkle::config::Config;
-use sparkle::crawl::search_dir;
+use sparkle::crawl::{group_by_tree, search_dir, search_location, search_path, search_rules};
 use std::fs;
 use std::io::Write;
+use std::os::unix::fs::MetadataExt;
 use std::path::Path;
 use tempfile::{tempdir, NamedTempFile};
 
@@ -143,4 +144,1088 @@ actions = [
     let results = search_dir(nonexistent_path, &config, rule, true).unwrap();
     
     assert_eq!(results.len(), 0);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_workflow_encoding_filter_and_transcode() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    let legacy_file = temp_path.join("legacy.txt");
+    fs::write(&legacy_file, b"caf\xE9 cr\xE8me br\xFBl\xE9e").unwrap();
+    fs::write(temp_path.join("modern.txt"), "café crème brûlée").unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "transcode_legacy"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ encoding = "windows-1252" }}
+]
+actions = [
+    "transcode_to_utf8"
+]
+"#, temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let rule = &config.rules[0];
+
+    let results = search_dir(temp_path, &config, rule, true).unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].path, legacy_file);
+    assert_eq!(fs::read_to_string(&legacy_file).unwrap(), "café crème brûlée");
+}
+
+#[test]
+fn test_workflow_text_detected_only_when_needed() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    fs::write(temp_path.join("notes.txt"), "plain notes").unwrap();
+    fs::write(temp_path.join("other.txt"), "other text").unwrap();
+    fs::write(temp_path.join("notes.jpg"), b"\xFF\xD8\xFF").unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "text"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ name_contains = "notes" }},
+    {{ encoding = "utf-8" }},
+    {{ older_than = "1d", timestamp = "taken" }}
+]
+actions = [
+    {{ echo = "Found text" }}
+]
+"#, temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let rule = &config.rules[0];
+
+    let mut results = search_dir(temp_path, &config, rule, true).unwrap();
+    results.sort_by(|a, b| a.path.cmp(&b.path));
+
+    // the name already matched, so the files were never opened, and the
+    // encoding matched before the EXIF data were looked for
+    let info = |i: usize| results[i].content_info.as_ref().unwrap();
+    assert_eq!(results.len(), 3);
+    assert_eq!(results[0].path, temp_path.join("notes.jpg"));
+    assert!(!info(0).text_detected && !info(0).media_detected);
+    assert_eq!(results[1].path, temp_path.join("notes.txt"));
+    assert!(!info(1).text_detected && !info(1).media_detected);
+    assert_eq!(results[2].path, temp_path.join("other.txt"));
+    assert!(info(2).text_detected && !info(2).media_detected);
+}
+
+#[test]
+fn test_workflow_content_contains_filter() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    let invoice = temp_path.join("scan_001.txt");
+    fs::write(&invoice, "ACME Corp\nINVOICE #1234\nTotal: 42.00\n").unwrap();
+    fs::write(temp_path.join("scan_002.txt"), "Meeting notes\n").unwrap();
+    fs::write(temp_path.join("scan_003.bin"), b"\0\0INVOICE").unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "find_invoices"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ content_regex = "^INVOICE #[0-9]+$", max_bytes = 1024 }}
+]
+actions = [
+    {{ echo = "Found invoice" }}
+]
+"#, temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let rule = &config.rules[0];
+
+    let results = search_dir(temp_path, &config, rule, true).unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].path, invoice);
+}
+
+#[test]
+fn test_workflow_category_filters() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    fs::write(temp_path.join("main.rs"), "fn main() {}").unwrap();
+    fs::write(temp_path.join("app.js"), "console.log(1)").unwrap();
+    fs::write(temp_path.join("notes.txt"), "some notes").unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "small_code"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ file_type = ["code", "configuration"] }}
+]
+actions = [
+    {{ echo = "Found code" }}
+]
+
+[[rules]]
+name = "tiny_files"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ size_category = "tiny" }}
+]
+actions = [
+    {{ echo = "Found tiny file" }}
+]
+"#, temp_path.display(), temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+
+    let code_results = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+    let tiny_results = search_dir(temp_path, &config, &config.rules[1], true).unwrap();
+
+    assert_eq!(code_results.len(), 2);
+    assert!(code_results.iter().all(|r| r.path.extension().unwrap() != "txt"));
+    assert_eq!(tiny_results.len(), 3);
+}
+
+#[test]
+fn test_workflow_xattr_tagging() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    let download = temp_path.join("download.pdf");
+    fs::write(&download, "pdf").unwrap();
+
+    // not every filesystem supports user xattrs
+    if xattr::set(&download, "user.sparkle.probe", b"1").is_err() {
+        return;
+    }
+    xattr::remove(&download, "user.sparkle.probe").unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "tag"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ xattr_missing = "user.sparkle.rule" }}
+]
+actions = [
+    {{ set_xattr = {{ name = "user.sparkle.rule", value = "tag" }} }}
+]
+
+[[rules]]
+name = "tagged"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ xattr_equals = {{ name = "user.sparkle.rule", value = "tag" }} }}
+]
+actions = [
+    {{ remove_xattr = "user.sparkle.rule" }}
+]
+"#, temp_path.display(), temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+
+    let tagged = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+    assert_eq!(tagged.len(), 1);
+    assert_eq!(xattr::get(&download, "user.sparkle.rule").unwrap(), Some(b"tag".to_vec()));
+
+    // second run skips the file that is already tagged
+    let retagged = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+    assert_eq!(retagged.len(), 0);
+
+    let untagged = search_dir(temp_path, &config, &config.rules[1], true).unwrap();
+    assert_eq!(untagged.len(), 1);
+    assert_eq!(xattr::get(&download, "user.sparkle.rule").unwrap(), None);
+}
+
+#[test]
+fn test_workflow_age_range_filter() {
+    use std::time::{Duration, SystemTime};
+
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    let set_age = |name: &str, days: u64| {
+        let path = temp_path.join(name);
+        fs::write(&path, "x").unwrap();
+        let mtime = SystemTime::now() - Duration::from_secs(days * 86_400);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+        path
+    };
+    set_age("fresh.txt", 0);
+    let in_range = set_age("ten_days.txt", 10);
+    set_age("ancient.txt", 400);
+
+    let config_content = format!(r#"
+[[rules]]
+name = "between_one_and_two_weeks"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ older_than = "1w", newer_than = "2w", timestamp = "modified" }}
+]
+actions = [
+    {{ echo = "Found file" }}
+]
+"#, temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let results = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+
+    assert_eq!(results.len(), 1);
+    assert_eq!(results[0].path, in_range);
+}
+
+#[test]
+fn test_workflow_conditions_in_one_table() {
+    use std::time::{Duration, SystemTime};
+
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    let write = |name: &str, size: usize, days: u64| {
+        let path = temp_path.join(name);
+        fs::write(&path, "x".repeat(size)).unwrap();
+        let mtime = SystemTime::now() - Duration::from_secs(days * 86_400);
+        fs::File::options().write(true).open(&path).unwrap().set_modified(mtime).unwrap();
+        path
+    };
+    let old_log = write("old.log", 1, 40);
+    write("new.log", 1, 0);
+    write("old.txt", 1, 40);
+    let big = write("big.txt", 200, 0);
+
+    // all conditions of a table must hold, any one table is enough
+    let config_content = format!(r#"
+[[rules]]
+name = "old logs or big files"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ extension = "log", older_than = "30d" }},
+    {{ size_gt = 100 }}
+]
+actions = [
+    {{ echo = "Found file" }}
+]
+"#, temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let mut results: Vec<_> = search_dir(temp_path, &config, &config.rules[0], true)
+        .unwrap()
+        .into_iter()
+        .map(|ctx| ctx.path)
+        .collect();
+    results.sort();
+
+    assert_eq!(results, vec![big, old_log]);
+}
+
+#[test]
+fn test_workflow_owner_and_mode_filters() {
+    use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    let world_writable = temp_path.join("shared.txt");
+    fs::write(&world_writable, "x").unwrap();
+    fs::set_permissions(&world_writable, fs::Permissions::from_mode(0o666)).unwrap();
+    let private = temp_path.join("private.txt");
+    fs::write(&private, "x").unwrap();
+    fs::set_permissions(&private, fs::Permissions::from_mode(0o600)).unwrap();
+
+    let uid = fs::metadata(&private).unwrap().uid();
+    let owner = sparkle::accounts::user_name(uid).expect("test user has a passwd entry");
+
+    let config_content = format!(r#"
+[[rules]]
+name = "world_writable"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ mode_has = "o+w" }}
+]
+actions = [
+    {{ echo = "World writable" }}
+]
+
+[[rules]]
+name = "owned"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ owner = "{}" }}
+]
+actions = [
+    {{ echo = "Owned by me" }}
+]
+"#, temp_path.display(), temp_path.display(), owner);
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+
+    let writable = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+    assert_eq!(writable.len(), 1);
+    assert_eq!(writable[0].path, world_writable);
+
+    let owned = search_dir(temp_path, &config, &config.rules[1], true).unwrap();
+    assert_eq!(owned.len(), 2);
+}
+
+#[test]
+fn test_workflow_excludes_and_ignore_files() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    for dir in [".git", "node_modules/pkg", "src/build", "docs"] {
+        fs::create_dir_all(temp_path.join(dir)).unwrap();
+    }
+    fs::write(temp_path.join(".git/config.txt"), "x").unwrap();
+    fs::write(temp_path.join("node_modules/pkg/readme.txt"), "x").unwrap();
+    fs::write(temp_path.join("src/.gitignore"), "build/\n").unwrap();
+    fs::write(temp_path.join("src/build/out.txt"), "x").unwrap();
+    fs::write(temp_path.join("src/main.txt"), "x").unwrap();
+    fs::write(temp_path.join(".sparkleignore"), "secret.txt\n").unwrap();
+    fs::write(temp_path.join("docs/secret.txt"), "x").unwrap();
+    fs::write(temp_path.join("docs/notes.txt"), "x").unwrap();
+    fs::write(temp_path.join("docs/notes.bak.txt"), "x").unwrap();
+
+    let config_content = format!(r#"
+exclude = ["*.bak.txt"]
+
+[[rules]]
+name = "text_files"
+locations = ["{}"]
+subfolders = true
+exclude = ["node_modules"]
+filters = [
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ echo = "Found text file" }}
+]
+"#, temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let results = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+
+    let mut found: Vec<_> = results
+        .iter()
+        .map(|r| r.path.strip_prefix(temp_path).unwrap().to_path_buf())
+        .collect();
+    found.sort();
+    assert_eq!(found, vec![Path::new("docs/notes.txt"), Path::new("src/main.txt")]);
+}
+
+#[test]
+fn test_workflow_directory_rules() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let archive_dir = tempdir().unwrap();
+
+    fs::create_dir_all(temp_path.join("empty")).unwrap();
+    fs::create_dir_all(temp_path.join("project/src")).unwrap();
+    fs::write(temp_path.join("project/src/main.rs"), "fn main() {}").unwrap();
+    fs::write(temp_path.join("project/notes.txt"), "todo").unwrap();
+    fs::write(temp_path.join("loose.txt"), "x").unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "empty_dirs"
+locations = ["{}"]
+subfolders = true
+targets = "dirs"
+filters = [
+    {{ is_empty = true }}
+]
+actions = [
+    "delete"
+]
+
+[[rules]]
+name = "projects"
+locations = ["{}"]
+subfolders = true
+targets = "both"
+filters = [
+    {{ file_count_gt = 1 }},
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ archive = {{ destination = "{}" }} }},
+    "delete"
+]
+"#, temp_path.display(), temp_path.display(), archive_dir.path().display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+
+    let empty = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+    assert_eq!(empty.len(), 1);
+    assert!(!temp_path.join("empty").exists());
+    assert!(temp_path.join("project").exists());
+
+    // the project is archived as a whole, so notes.txt inside it is not visited
+    let mut handled: Vec<_> = search_dir(temp_path, &config, &config.rules[1], true)
+        .unwrap()
+        .iter()
+        .map(|r| r.path.clone())
+        .collect();
+    handled.sort();
+    assert_eq!(handled, vec![temp_path.join("loose.txt"), temp_path.join("project")]);
+    assert!(!temp_path.join("project").exists());
+    assert!(archive_dir.path().join("project.tar.gz").is_file());
+    assert!(archive_dir.path().join("loose.txt.tar.gz").is_file());
+}
+
+#[test]
+fn test_workflow_remove_empty_dirs() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let dest_dir = tempdir().unwrap();
+
+    for dir in ["already_empty", "a/b/c", "keep", "node_modules/pkg"] {
+        fs::create_dir_all(temp_path.join(dir)).unwrap();
+    }
+    fs::write(temp_path.join("a/b/c/report.pdf"), "pdf").unwrap();
+    fs::write(temp_path.join("a/summary.pdf"), "pdf").unwrap();
+    fs::write(temp_path.join("keep/notes.txt"), "x").unwrap();
+    fs::write(temp_path.join("keep/old.pdf"), "pdf").unwrap();
+    fs::write(temp_path.join("node_modules/pkg/manual.pdf"), "pdf").unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "pdfs"
+locations = [{{ path = "{}", remove_empty_dirs = true }}]
+subfolders = true
+exclude = ["node_modules/pkg"]
+filters = [
+    {{ extension = "pdf" }}
+]
+actions = [
+    {{ move = "{}" }}
+]
+"#, temp_path.display(), dest_dir.path().display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let rule = &config.rules[0];
+    let results = search_location(&rule.locations[0], &config, rule, true).unwrap();
+    assert_eq!(results.len(), 3);
+
+    assert!(!temp_path.join("a").exists());
+    assert!(temp_path.join("keep/notes.txt").exists());
+    assert!(temp_path.join("already_empty").is_dir());
+    assert!(temp_path.join("node_modules/pkg").is_dir());
+    assert!(temp_path.is_dir());
+}
+
+#[test]
+fn test_workflow_symlinks() {
+    use std::os::unix::fs::symlink;
+
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let outside = tempdir().unwrap();
+
+    fs::create_dir_all(temp_path.join("docs")).unwrap();
+    fs::write(temp_path.join("docs/notes.txt"), "x").unwrap();
+    fs::write(outside.path().join("linked.txt"), "x").unwrap();
+    // a loop back to the location and a link to a directory outside of it
+    symlink(temp_path, temp_path.join("docs/loop")).unwrap();
+    symlink(outside.path(), temp_path.join("outside")).unwrap();
+    symlink(temp_path.join("missing.txt"), temp_path.join("dangling.txt")).unwrap();
+    symlink(temp_path.join("docs/notes.txt"), temp_path.join("shortcut.txt")).unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "text_files"
+locations = ["{}"]
+subfolders = true
+filters = [
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ echo = "Found text file" }}
+]
+
+[[rules]]
+name = "followed"
+locations = ["{}"]
+subfolders = true
+follow_symlinks = true
+filters = [
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ echo = "Found text file" }}
+]
+
+[[rules]]
+name = "broken_links"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ broken_symlink = true }}
+]
+actions = [
+    "delete"
+]
+"#, temp_path.display(), temp_path.display(), temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+
+    let found = |rule| {
+        let mut found: Vec<_> = search_dir(temp_path, &config, &config.rules[rule], true)
+            .unwrap()
+            .iter()
+            .map(|r| (r.path.strip_prefix(temp_path).unwrap().to_path_buf(), r.metadata.is_symlink))
+            .collect();
+        found.sort();
+        found
+    };
+
+    assert_eq!(found(0), vec![
+        (Path::new("dangling.txt").to_path_buf(), true),
+        (Path::new("docs/notes.txt").to_path_buf(), false),
+        (Path::new("shortcut.txt").to_path_buf(), true),
+    ]);
+    // the loop is entered once at most, the outside directory is searched
+    assert_eq!(found(1), vec![
+        (Path::new("dangling.txt").to_path_buf(), true),
+        (Path::new("docs/notes.txt").to_path_buf(), false),
+        (Path::new("outside/linked.txt").to_path_buf(), false),
+        (Path::new("shortcut.txt").to_path_buf(), true),
+    ]);
+
+    let broken = search_dir(temp_path, &config, &config.rules[2], true).unwrap();
+    assert_eq!(broken.len(), 1);
+    assert!(fs::symlink_metadata(temp_path.join("dangling.txt")).is_err());
+    assert!(temp_path.join("shortcut.txt").exists());
+    assert!(outside.path().join("linked.txt").exists());
+}
+
+#[test]
+fn test_workflow_filesystem_boundaries() {
+    // needs a second filesystem, /dev/shm is tmpfs on Linux
+    if !Path::new("/dev/shm").is_dir() {
+        return;
+    }
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let shm_dir = tempfile::tempdir_in("/dev/shm").unwrap();
+    let same_device = fs::metadata(temp_path).unwrap().dev() == fs::metadata(shm_dir.path()).unwrap().dev();
+    if same_device {
+        // the temp dir is on /dev/shm itself, nothing to cross
+        return;
+    }
+
+    fs::write(temp_path.join("local.txt"), "x").unwrap();
+    fs::write(shm_dir.path().join("remote.txt"), "x").unwrap();
+    std::os::unix::fs::symlink(shm_dir.path(), temp_path.join("share")).unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "everything"
+locations = ["{}"]
+subfolders = true
+follow_symlinks = true
+filters = [
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ echo = "Found text file" }}
+]
+
+[[rules]]
+name = "same_filesystem"
+locations = ["{}"]
+subfolders = true
+follow_symlinks = true
+same_filesystem = true
+filters = [
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ echo = "Found text file" }}
+]
+
+[[rules]]
+name = "skip_tmpfs"
+locations = ["{}"]
+subfolders = true
+follow_symlinks = true
+skip_fstypes = ["tmpfs"]
+filters = [
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ echo = "Found text file" }}
+]
+"#, temp_path.display(), temp_path.display(), temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+
+    let all = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+    let same_fs = search_dir(temp_path, &config, &config.rules[1], true).unwrap();
+    let skipped = search_dir(temp_path, &config, &config.rules[2], true).unwrap();
+
+    assert_eq!(all.len(), 2);
+    assert_eq!(same_fs.len(), 1);
+    assert_eq!(same_fs[0].path, temp_path.join("local.txt"));
+    assert_eq!(skipped.len(), 1);
+    assert_eq!(skipped[0].path, temp_path.join("local.txt"));
+}
+
+#[test]
+fn test_workflow_search_single_path() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+
+    fs::create_dir_all(temp_path.join("build/out")).unwrap();
+    fs::create_dir_all(temp_path.join("docs")).unwrap();
+    fs::write(temp_path.join("build/out/log.txt"), "x").unwrap();
+    fs::write(temp_path.join("docs/notes.txt"), "x").unwrap();
+    fs::write(temp_path.join("top.txt"), "x").unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "nested"
+locations = ["{}"]
+subfolders = true
+exclude = ["build/"]
+filters = [
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ echo = "Found text file" }}
+]
+
+[[rules]]
+name = "top_level"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ echo = "Found text file" }}
+]
+"#, temp_path.display(), temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let search = |rule: usize, path: &str| {
+        let rule = &config.rules[rule];
+        search_path(&temp_path.join(path), &rule.locations[0], &config, rule, true).unwrap().len()
+    };
+
+    assert_eq!(search(0, "docs/notes.txt"), 1);
+    assert_eq!(search(0, "build/out/log.txt"), 0);
+    assert_eq!(search(0, "docs"), 1);
+    assert_eq!(search(1, "top.txt"), 1);
+    assert_eq!(search(1, "docs/notes.txt"), 0);
+    assert_eq!(search(1, "missing.txt"), 0);
+}
+
+#[test]
+fn test_workflow_new_since_last_run() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let cache_dir = tempdir().unwrap();
+
+    fs::write(temp_path.join("old.txt"), "first run").unwrap();
+    fs::write(temp_path.join("edited.txt"), "first run").unwrap();
+
+    let config_content = format!(r#"
+cache_dir = "{}"
+
+[[rules]]
+name = "new_files"
+locations = [{{ path = "{}", cache = true }}]
+subfolders = false
+filters = [
+    {{ new_since_last_run = true }}
+]
+actions = [
+    {{ echo = "New file" }}
+]
+"#, cache_dir.path().display(), temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let rule = &config.rules[0];
+    let search = || {
+        let mut names: Vec<String> = search_location(&rule.locations[0], &config, rule, true)
+            .unwrap()
+            .iter()
+            .map(|ctx| ctx.path.file_name().unwrap().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    };
+
+    // everything is new on the first run, nothing on the second
+    assert_eq!(search(), ["edited.txt", "old.txt"]);
+    assert!(search().is_empty());
+
+    fs::write(temp_path.join("edited.txt"), "second run, longer").unwrap();
+    fs::write(temp_path.join("added.txt"), "second run").unwrap();
+    assert_eq!(search(), ["added.txt", "edited.txt"]);
+    assert!(search().is_empty());
+}
+
+#[test]
+fn test_workflow_rule_modes() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let pdfs = tempdir().unwrap();
+    let backup = tempdir().unwrap();
+
+    let config = |mode: &str, stop: bool| {
+        let config_content = format!(r#"
+mode = "{}"
+
+[[rules]]
+name = "pdfs"
+locations = ["{}"]
+subfolders = false
+stop = {}
+filters = [
+    {{ extension = "pdf" }}
+]
+actions = [
+    {{ set_permissions = 600 }}
+]
+
+[[rules]]
+name = "backup"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ extension = "pdf" }},
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ copy = "{}" }}
+]
+
+[[rules]]
+name = "file_pdfs"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ extension = "pdf" }}
+]
+actions = [
+    {{ move = "{}" }}
+]
+"#, mode, temp_path.display(), stop, temp_path.display(), backup.path().display(),
+        temp_path.display(), pdfs.path().display());
+        let mut config_file = NamedTempFile::new().unwrap();
+        write!(config_file, "{}", config_content).unwrap();
+        Config::new(config_file.path().to_str().unwrap()).unwrap()
+    };
+    let run = |config: &Config| {
+        fs::write(temp_path.join("report.pdf"), "pdf").unwrap();
+        fs::write(temp_path.join("notes.txt"), "txt").unwrap();
+        let trees = group_by_tree(&config.rules);
+        assert_eq!(trees.len(), 1);
+        let mut handled: Vec<String> = search_rules(&trees[0], config, true)
+            .unwrap()
+            .iter()
+            .map(|h| format!("{} {}", h.rule.name, h.context.path.file_name().unwrap().to_string_lossy()))
+            .collect();
+        handled.sort();
+        for dir in [temp_path, pdfs.path(), backup.path()] {
+            for entry in fs::read_dir(dir).unwrap() {
+                fs::remove_file(entry.unwrap().path()).unwrap();
+            }
+        }
+        handled
+    };
+
+    // every matching rule runs, in order, on the same file
+    assert_eq!(
+        run(&config("all", false)),
+        ["backup notes.txt", "backup report.pdf", "file_pdfs report.pdf", "pdfs report.pdf"]
+    );
+    // only the first rule that matches a file handles it
+    assert_eq!(run(&config("first_match", false)), ["backup notes.txt", "pdfs report.pdf"]);
+    // a rule with stop keeps later rules away from its files
+    assert_eq!(run(&config("all", true)), ["backup notes.txt", "pdfs report.pdf"]);
+}
+
+#[test]
+fn test_workflow_nested_locations() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let inbox = temp_path.join("inbox");
+    let dest_dir = tempdir().unwrap();
+    let other_dir = tempdir().unwrap();
+
+    fs::create_dir(&inbox).unwrap();
+    fs::write(temp_path.join("a.txt"), "txt").unwrap();
+    fs::write(temp_path.join("c.pdf"), "pdf").unwrap();
+    fs::write(inbox.join("b.pdf"), "pdf").unwrap();
+    fs::write(inbox.join("d.txt"), "txt").unwrap();
+
+    let config_content = format!(r#"
+mode = "first_match"
+
+[[rules]]
+name = "top_level_text"
+locations = ["{}"]
+subfolders = false
+filters = [
+    {{ extension = "txt" }}
+]
+actions = [
+    {{ echo = "Found text file" }}
+]
+
+[[rules]]
+name = "inbox_pdfs"
+locations = ["{}", "{}"]
+subfolders = true
+filters = [
+    {{ extension = "pdf" }}
+]
+actions = [
+    {{ move = "{}" }}
+]
+
+[[rules]]
+name = "all_pdfs"
+locations = ["{}"]
+subfolders = true
+filters = [
+    {{ extension = "pdf" }}
+]
+actions = [
+    {{ echo = "Found pdf" }}
+]
+"#, temp_path.display(), inbox.display(), other_dir.path().display(),
+    dest_dir.path().display(), temp_path.display());
+
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+    let trees = group_by_tree(&config.rules);
+    assert_eq!(trees.len(), 2);
+    assert_eq!(trees[0].root, temp_path);
+    assert_eq!(trees[0].locations(), [temp_path, inbox.as_path()]);
+    assert_eq!(trees[1].root, other_dir.path());
+
+    let mut handled: Vec<String> = search_rules(&trees[0], &config, true)
+        .unwrap()
+        .iter()
+        .map(|h| format!("{} {}", h.rule.name, h.context.path.strip_prefix(temp_path).unwrap().display()))
+        .collect();
+    handled.sort();
+    // the inbox rule claims its pdf before the rule for the whole tree sees it
+    assert_eq!(handled, ["all_pdfs c.pdf", "inbox_pdfs inbox/b.pdf", "top_level_text a.txt"]);
+    assert!(dest_dir.path().join("b.pdf").exists());
+    assert!(temp_path.join("c.pdf").exists());
+}
+
+#[test]
+fn test_workflow_json_and_yaml_configs() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let dest_dir = tempdir().unwrap();
+
+    fs::write(temp_path.join("report.pdf"), "pdf").unwrap();
+    fs::write(temp_path.join("notes.txt"), "text").unwrap();
+
+    let json = format!(r#"{{
+  "rules": [
+    {{
+      "name": "pdfs",
+      "locations": ["{}"],
+      "subfolders": false,
+      "filters": [{{ "extension": "pdf" }}],
+      "actions": [{{ "copy": "{}" }}, {{ "echo": "copied" }}]
+    }}
+  ]
+}}"#, temp_path.display(), dest_dir.path().display());
+    let yaml = format!(r#"
+rules:
+  - name: pdfs
+    locations: ["{}"]
+    subfolders: false
+    filters:
+      - extension: pdf
+    actions:
+      - copy: "{}"
+      - echo: copied
+"#, temp_path.display(), dest_dir.path().display());
+
+    for (suffix, contents) in [(".json", json), (".yml", yaml)] {
+        let mut config_file = tempfile::Builder::new().suffix(suffix).tempfile().unwrap();
+        write!(config_file, "{}", contents).unwrap();
+
+        let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+        let results = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+        assert_eq!(results.len(), 1, "{}", suffix);
+        assert_eq!(results[0].path, temp_path.join("report.pdf"));
+        assert!(dest_dir.path().join("report.pdf").exists());
+        fs::remove_file(dest_dir.path().join("report.pdf")).unwrap();
+    }
+}
+
+#[test]
+fn test_workflow_included_rules() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let config_dir = tempdir().unwrap();
+    let dest_dir = tempdir().unwrap();
+
+    fs::write(temp_path.join("report.pdf"), "pdf").unwrap();
+    fs::write(temp_path.join("report.pdf.part"), "pdf").unwrap();
+    fs::write(temp_path.join("notes.txt"), "text").unwrap();
+
+    fs::create_dir(config_dir.path().join("rules.d")).unwrap();
+    fs::write(config_dir.path().join("rules.d").join("pdfs.toml"), r#"
+[[rules]]
+name = "pdfs"
+extends = "inbox"
+filters = [{ name_contains = "pdf" }]
+"#).unwrap();
+    let config_path = config_dir.path().join("sparkle.toml");
+    fs::write(&config_path, format!(r#"
+include = ["rules.d/*.toml"]
+
+[defaults]
+subfolders = false
+exclude = ["*.part"]
+
+[templates.inbox]
+locations = ["{}"]
+actions = [{{ copy = "{}" }}]
+"#, temp_path.display(), dest_dir.path().display())).unwrap();
+
+    let config = Config::new(config_path.to_str().unwrap()).unwrap();
+    assert_eq!(config.rules.len(), 1);
+    let results = search_dir(temp_path, &config, &config.rules[0], true).unwrap();
+    assert_eq!(results.len(), 1);
+    assert!(dest_dir.path().join("report.pdf").exists());
+    assert!(!dest_dir.path().join("report.pdf.part").exists());
+}
+
+#[test]
+fn test_workflow_stop_on_directory() {
+    let temp_dir = tempdir().unwrap();
+    let temp_path = temp_dir.path();
+    let inbox = temp_path.join("project").join("inbox");
+    fs::create_dir_all(&inbox).unwrap();
+    fs::write(temp_path.join("project").join("a.pdf"), "pdf").unwrap();
+    fs::write(inbox.join("b.pdf"), "pdf").unwrap();
+
+    let config_content = format!(r#"
+[[rules]]
+name = "pdfs"
+locations = ["{0}"]
+subfolders = true
+filters = [{{ extension = "pdf" }}]
+actions = [{{ echo = "pdf" }}]
+
+[[rules]]
+name = "projects"
+locations = ["{0}"]
+subfolders = false
+targets = "dirs"
+stop = true
+filters = [{{ name_contains = "project" }}]
+actions = [{{ echo = "project" }}]
+
+[[rules]]
+name = "later_pdfs"
+locations = ["{0}"]
+subfolders = true
+filters = [{{ extension = "pdf" }}]
+actions = [{{ echo = "pdf" }}]
+
+[[rules]]
+name = "inbox"
+locations = ["{1}"]
+subfolders = false
+filters = [{{ extension = "pdf" }}]
+actions = [{{ echo = "inbox" }}]
+"#, temp_path.display(), inbox.display());
+    let mut config_file = NamedTempFile::new().unwrap();
+    write!(config_file, "{}", config_content).unwrap();
+    let config = Config::new(config_file.path().to_str().unwrap()).unwrap();
+
+    let trees = group_by_tree(&config.rules);
+    assert_eq!(trees.len(), 1);
+    let mut handled: Vec<String> = search_rules(&trees[0], &config, true)
+        .unwrap()
+        .iter()
+        .map(|h| format!("{} {}", h.rule.name, h.context.path.strip_prefix(temp_path).unwrap().display()))
+        .collect();
+    handled.sort();
+    // the directory a rule with stop claims is still searched by the rules
+    // before it and for locations inside it, only the later rules skip it
+    assert_eq!(
+        handled,
+        [
+            "inbox project/inbox/b.pdf",
+            "pdfs project/a.pdf",
+            "pdfs project/inbox/b.pdf",
+            "projects project",
+        ]
+    );
+}